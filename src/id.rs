@@ -30,21 +30,64 @@ impl Id {
         let mut distance = Self::zero();
         let mut carry = 0;
         for i in (0..ID_SIZE).rev() {
-            let (result, new_carry) = self.id[i].overflowing_sub(other.id[i] + carry);
+            let (result, borrow) = self.id[i].overflowing_sub(other.id[i]);
+            let (result, carry_borrow) = result.overflowing_sub(carry);
             distance[i] = result;
-            carry = new_carry as u8;
+            carry = (borrow || carry_borrow) as u8;
         }
         distance
     }
 
+    /// compute the distance between two IDs on the ring,
+    /// the shortest between going clockwise and counterclockwise
+    pub fn ring_distance(&self, other: &Self) -> Self {
+        std::cmp::min(self.distance(other), other.distance(self))
+    }
+
     /// Get the i-th digith of the ID, a digit is 4bits
+    /// the first digit is the most significant one
     pub fn get_digit(&self, i: usize) -> u8 {
         let byte = self.id[i / 2];
-        if i % 2 == 0 {
-            byte & 0x0F
-        } else {
+        if i.is_multiple_of(2) {
             byte >> 4
+        } else {
+            byte & 0x0F
+        }
+    }
+
+    /// Get the number of leading digits shared by the two IDs
+    pub fn shared_prefix_len(&self, other: &Self) -> usize {
+        (0..ID_SIZE * 2).find(|&i| self.get_digit(i) != other.get_digit(i)).unwrap_or(ID_SIZE * 2)
+    }
+
+    /// compute the sum of two IDs modulo 2^(8*ID_SIZE)
+    pub fn wrapping_add(&self, other: &Self) -> Self {
+        let mut sum = Self::zero();
+        let mut carry = 0;
+        for i in (0..ID_SIZE).rev() {
+            let (result, overflow) = self.id[i].overflowing_add(other.id[i]);
+            let (result, carry_overflow) = result.overflowing_add(carry);
+            sum[i] = result;
+            carry = (overflow || carry_overflow) as u8;
+        }
+        sum
+    }
+
+    /// Get the ID halfway from self to other going clockwise
+    pub fn midpoint(&self, other: &Self) -> Self {
+        let distance = other.distance(self);
+        let mut half = Self::zero();
+        let mut carry = 0;
+        for i in 0..ID_SIZE {
+            half[i] = (distance.id[i] >> 1) | carry;
+            carry = (distance.id[i] & 1) << 7;
         }
+        self.wrapping_add(&half)
+    }
+
+    /// Check if the ID is between start and end going clockwise, both included
+    pub fn is_between(&self, start: &Self, end: &Self) -> bool {
+        self.distance(start) <= end.distance(start)
     }
 }
 
@@ -60,12 +103,12 @@ impl FromStr for Id {
             }
             if (i % 2) == 0 {
                 id[i / 2] = match c.to_digit(16) {
-                    Some(d) => d as u8,
+                    Some(d) => (d as u8) << 4,
                     None => return Err(format!("Invalid character: {}, expected hex digit", c))
                 };
             } else {
                 id[i / 2] |= match c.to_digit(16) {
-                    Some(d) => (d as u8) << 4,
+                    Some(d) => d as u8,
                     None => return Err(format!("Invalid character: {}, expected hex digit", c))
                 };
             }
@@ -91,7 +134,7 @@ impl Index<usize> for Id {
     type Output = u8;
 
     fn index(&self, index: usize) -> &Self::Output {
-        &self.id[index / 2]
+        &self.id[index]
     }
 }
 
@@ -117,4 +160,56 @@ impl Ord for Id {
         }
         std::cmp::Ordering::Equal
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digits_in_numeric_order() {
+        let id = Id::from_str("0123-4567-89ab-cdef").unwrap();
+        let digits: Vec<u8> = (0..ID_SIZE * 2).map(|i| id.get_digit(i)).collect();
+        assert_eq!(digits, (0..0x10).collect::<Vec<u8>>());
+        assert_eq!(id[0], 0x01);
+
+        // the first differing digit decides the order of two ids
+        let lower = Id::from_str("0123-4567-89ab-cdef").unwrap();
+        let higher = Id::from_str("0123-4567-89ac-0000").unwrap();
+        assert!(lower < higher);
+        assert_eq!(lower.shared_prefix_len(&higher), 11);
+        assert!(lower.get_digit(11) < higher.get_digit(11));
+    }
+
+    #[test]
+    fn test_string_round_trip() {
+        let id = Id::from_key("key");
+        assert_eq!(Id::from_str(&id.to_string()).unwrap(), id);
+        assert_eq!(Id::from_str("0123-4567-89ab-cdef").unwrap().to_string(), "0123456789abcdef");
+        assert!(Id::from_str("0123-4567-89ab-cde").is_err());
+        assert!(Id::from_str("0123-4567-89ab-cdeg").is_err());
+    }
+
+    #[test]
+    fn test_distance_borrows() {
+        // the borrow of the last byte crosses a 0x00 byte and a 0xff byte
+        let id = Id::from_str("0000-0000-0000-ff00").unwrap();
+        let other = Id::from_str("0000-0000-0000-ff01").unwrap();
+        assert_eq!(id.distance(&other), Id::from_str("ffff-ffff-ffff-ffff").unwrap());
+        assert_eq!(other.distance(&id), Id::from_str("0000-0000-0000-0001").unwrap());
+
+        let id = Id::from_str("0000-0000-0100-0000").unwrap();
+        let other = Id::from_str("0000-0000-00ff-0001").unwrap();
+        assert_eq!(id.distance(&other), Id::from_str("0000-0000-0000-ffff").unwrap());
+    }
+
+    #[test]
+    fn test_ring_distance_wraps_around() {
+        let first = Id::from_str("ffff-ffff-ffff-fff0").unwrap();
+        let second = Id::from_str("0000-0000-0000-0010").unwrap();
+        let expected = Id::from_str("0000-0000-0000-0020").unwrap();
+        assert_eq!(first.ring_distance(&second), expected);
+        assert_eq!(second.ring_distance(&first), expected);
+        assert_eq!(first.ring_distance(&first), Id::zero());
+    }
+}
@@ -0,0 +1,108 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use serde::{Deserialize, Serialize};
+
+use crate::id::Id;
+
+use super::storage::Storage;
+
+/// Above this number of entries a range of the Merkle tree is split in two halves.
+pub const MERKLE_LEAF_SIZE: usize = 0x10;
+
+/// A range of keys going clockwise from start to end, both included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct KeyRange {
+    pub start: Id,
+    pub end: Id,
+}
+
+impl KeyRange {
+    pub fn new(start: Id, end: Id) -> Self {
+        Self { start, end }
+    }
+
+    pub fn contains(&self, key: &Id) -> bool {
+        key.is_between(&self.start, &self.end)
+    }
+
+    /// Split the range in two halves, None if the range contains a single key.
+    pub fn split(&self) -> Option<(KeyRange, KeyRange)> {
+        if self.start == self.end {
+            return None;
+        }
+        let middle = self.start.midpoint(&self.end);
+        let after_middle = middle.wrapping_add(&Id::new([0, 0, 0, 0, 0, 0, 0, 1]));
+        Some((KeyRange::new(self.start, middle), KeyRange::new(after_middle, self.end)))
+    }
+}
+
+/// Get the keys stored in the range with the version of their entry, tombstones included,
+/// sorted clockwise from the start of the range.
+pub fn digests(storage: &Storage, range: &KeyRange) -> Vec<(Id, u64)> {
+    let mut digests: Vec<(Id, u64)> = storage.iter()
+        .filter(|(key, _)| range.contains(key))
+        .map(|(key, entry)| (*key, entry.version))
+        .collect();
+    digests.sort_by_key(|(key, _)| key.distance(&range.start));
+    digests
+}
+
+/// Compute the root hash of the Merkle tree over the digests of a range.
+/// A range with more than MERKLE_LEAF_SIZE entries is split in two halves and the hashes of the halves are combined,
+/// so two nodes compute the same hash, and the same tree, only if they store the same entries.
+pub fn merkle_hash(digests: &[(Id, u64)], range: &KeyRange) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    match range.split() {
+        Some((left, right)) if digests.len() > MERKLE_LEAF_SIZE => {
+            let middle = digests.partition_point(|(key, _)| left.contains(key));
+            merkle_hash(&digests[..middle], &left).hash(&mut hasher);
+            merkle_hash(&digests[middle..], &right).hash(&mut hasher);
+        },
+        _ => digests.hash(&mut hasher),
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, time::SystemTime};
+
+    use crate::network::storage::{Entry, StorageOperation};
+
+    use super::*;
+
+    fn storage(keys: impl Iterator<Item = u64>) -> Storage {
+        let mut storage = Storage::new();
+        for key in keys {
+            storage.merge(Id::from_key(key), Entry { value: Some(key.to_be_bytes().to_vec()), version: key }, SystemTime::now());
+        }
+        storage
+    }
+
+    #[test]
+    fn test_split_wraps_around() {
+        let range = KeyRange::new(Id::from_str("f000-0000-0000-0000").unwrap(), Id::from_str("1000-0000-0000-0000").unwrap());
+        let (left, right) = range.split().unwrap();
+        assert_eq!(left, KeyRange::new(range.start, Id::zero()));
+        assert_eq!(right, KeyRange::new(Id::from_str("0000-0000-0000-0001").unwrap(), range.end));
+        assert!(left.contains(&Id::from_str("ff00-0000-0000-0000").unwrap()));
+        assert!(!left.contains(&Id::from_str("0100-0000-0000-0000").unwrap()));
+        assert!(right.contains(&Id::from_str("0100-0000-0000-0000").unwrap()));
+        assert_eq!(KeyRange::new(range.start, range.start).split(), None);
+    }
+
+    #[test]
+    fn test_merkle_hash_compares_entries() {
+        let range = KeyRange::new(Id::zero(), Id::from_str("ffff-ffff-ffff-ffff").unwrap());
+        let hash = |storage: &Storage| merkle_hash(&digests(storage, &range), &range);
+
+        let mut first = storage(0..100);
+        let second = storage((0..100).rev());
+        assert_eq!(hash(&first), hash(&second));
+
+        first.execute(Id::from_key(42u64), StorageOperation::Put { value: vec![0] }, SystemTime::now());
+        assert_ne!(hash(&first), hash(&second));
+        first.execute(Id::from_key(42u64), StorageOperation::Delete, SystemTime::now());
+        assert_ne!(hash(&first), hash(&second));
+    }
+}
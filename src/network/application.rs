@@ -0,0 +1,42 @@
+use crate::id::Id;
+
+use super::peer::Peer;
+
+/// Identifies an application among the ones running on the same node,
+/// every message carries the id of the application that should handle it
+pub type ApplicationId = u16;
+
+/// A change in the leaf set of the local node, notified to the applications
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LeafSetChange {
+    /// The peer was added to the leaf set
+    Joined(Peer),
+    /// The peer was removed from the leaf set
+    Left(Peer),
+}
+
+/// An application running on top of the overlay.
+/// The framework calls these methods (upcalls) while routing messages.
+pub trait Application: Send + Sync {
+    /// Called on the node that is numerically closest to the key of the message.
+    fn deliver(&self, key: Id, payload: Vec<u8>);
+
+    /// Called on every node that forwards the message, before sending it to the next hop.
+    /// Return the payload to forward (possibly modified) or None to drop the message.
+    fn forward(&self, key: Id, payload: Vec<u8>, next_hop: &Peer) -> Option<Vec<u8>> {
+        let _ = (key, next_hop);
+        Some(payload)
+    }
+
+    /// Called on the node that is numerically closest to the key of a request, origin is the peer that sent it.
+    /// Return the response to send back to the origin, or None if the application does not handle requests.
+    fn request(&self, key: Id, payload: Vec<u8>, origin: &Peer) -> Option<Vec<u8>> {
+        let _ = (key, payload, origin);
+        None
+    }
+
+    /// Called when the leaf set of the local node changes.
+    fn update(&self, change: LeafSetChange) {
+        let _ = change;
+    }
+}
@@ -6,4 +6,40 @@ pub struct Config {
     pub entry_addr: SocketAddr,
     pub socket_read_timeout: std::time::Duration,
     pub socket_write_timeout: std::time::Duration,
+    /// How long to wait for the join process to complete
+    pub join_timeout: std::time::Duration,
+    /// How long to wait for the response to a request routed to another peer
+    pub request_timeout: std::time::Duration,
+    /// On how many peers each key of the key-value store is stored, the closest one included
+    pub replication_factor: usize,
+    /// How often the peers in the routing table are pinged
+    pub probe_interval: std::time::Duration,
+    /// How long to wait for a pong before considering the probe missed
+    pub probe_timeout: std::time::Duration,
+    /// How many probes in a row a peer can miss before being considered dead
+    pub max_missed_probes: u8,
+    /// How long a dead peer is ignored when the other peers advertise it
+    pub dead_peer_timeout: std::time::Duration,
+    /// How often the stored keys are compared with the replicas to repair the missing ones
+    pub anti_entropy_interval: std::time::Duration,
+    /// How long a deleted key is remembered, so that the replicas that missed the deletion do not bring it back
+    pub tombstone_timeout: std::time::Duration,
+    /// How long to wait for the acknowledgement of a reliable message before the first retransmission,
+    /// the wait doubles after each retransmission.
+    /// A join request is sent again every retransmission_timeout until the join completes
+    pub retransmission_timeout: std::time::Duration,
+    /// How many times a reliable message is retransmitted before giving up
+    pub max_retransmissions: u8,
+    /// How long a delivered reliable message is remembered to suppress its retransmissions
+    pub duplicate_timeout: std::time::Duration,
+    /// How long to wait for the missing fragments of a packet larger than the MTU before dropping it
+    pub reassembly_timeout: std::time::Duration,
+    /// How many bytes the packets being reassembled can hold at most,
+    /// each one is charged for all its fragments from the first one received
+    pub max_reassembly_bytes: usize,
+    /// How many threads handle the received packets, 0 to handle them on the receiving thread
+    pub workers: usize,
+    /// How many received packets each worker can hold before the new ones are dropped,
+    /// at least 1 if there are workers, start fails otherwise
+    pub worker_queue_size: usize,
 }
\ No newline at end of file
@@ -0,0 +1,182 @@
+use std::{collections::HashMap, net::SocketAddr, time::{Duration, Instant}};
+
+use anyhow::bail;
+
+use super::packet::Packet;
+
+/// The size of the data in a fragment, small enough for the serialized Fragment packet to fit in the MTU.
+pub const FRAGMENT_SIZE: usize = 1400;
+/// How many packets from the same peer can be reassembled at the same time.
+pub const MAX_PARTIALS_PER_PEER: usize = 0x10;
+
+/// Split a serialized packet in Fragment packets of at most FRAGMENT_SIZE bytes of data each.
+pub fn fragment(message_id: u64, data: &[u8]) -> anyhow::Result<Vec<Packet>> {
+    let chunks: Vec<&[u8]> = data.chunks(FRAGMENT_SIZE).collect();
+    let count = match u16::try_from(chunks.len()) {
+        Ok(count) => count,
+        Err(_) => bail!("Packet too large to be fragmented: {} bytes", data.len()),
+    };
+    Ok(chunks.into_iter().enumerate().map(|(index, chunk)| Packet::Fragment {
+        message_id,
+        index: index as u16,
+        count,
+        data: chunk.to_vec(),
+    }).collect())
+}
+
+#[derive(Debug)]
+struct Partial {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    started_at: Instant,
+    /// the bytes charged for the packet, as if every fragment was full
+    reserved: usize,
+}
+
+/// Reassembles the fragments received from the other peers.
+/// The packets that are not complete within the timeout are dropped.
+/// Each packet is charged count * FRAGMENT_SIZE bytes as soon as its first fragment arrives,
+/// the packets that would make the charged bytes exceed max_bytes are dropped,
+/// as are the packets from a peer already reassembling MAX_PARTIALS_PER_PEER packets.
+#[derive(Debug)]
+pub struct Reassembly {
+    partial: HashMap<(SocketAddr, u64), Partial>,
+    /// the bytes charged for the partial packets
+    bytes: usize,
+    timeout: Duration,
+    max_bytes: usize,
+}
+
+impl Reassembly {
+    pub fn new(timeout: Duration, max_bytes: usize) -> Self {
+        Self {
+            partial: HashMap::new(),
+            bytes: 0,
+            timeout,
+            max_bytes,
+        }
+    }
+
+    /// Drop the partial packets older than the timeout.
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let mut freed = 0;
+        self.partial.retain(|_, partial| {
+            let keep = now.duration_since(partial.started_at) < timeout;
+            if !keep {
+                freed += partial.reserved;
+            }
+            keep
+        });
+        self.bytes -= freed;
+    }
+
+    /// Add a fragment sent by the peer at addr.
+    /// Returns the serialized packet once every fragment has been received.
+    pub fn add(&mut self, addr: SocketAddr, message_id: u64, index: u16, count: u16, data: Vec<u8>, now: Instant) -> Option<Vec<u8>> {
+        self.expire(now);
+        if index >= count || data.len() > FRAGMENT_SIZE {
+            return None;
+        }
+        if !self.partial.contains_key(&(addr, message_id)) {
+            let reserved = count as usize * FRAGMENT_SIZE;
+            let partials = self.partial.keys().filter(|(source, _)| *source == addr).count();
+            if self.bytes + reserved > self.max_bytes || partials >= MAX_PARTIALS_PER_PEER {
+                return None;
+            }
+            self.bytes += reserved;
+            self.partial.insert((addr, message_id), Partial {
+                fragments: vec![None; count as usize],
+                received: 0,
+                started_at: now,
+                reserved,
+            });
+        }
+        let partial = self.partial.get_mut(&(addr, message_id))?;
+        if partial.fragments.len() != count as usize {
+            return None;
+        }
+        let slot = partial.fragments.get_mut(index as usize)?;
+        if slot.is_some() {
+            return None;
+        }
+        *slot = Some(data);
+        partial.received += 1;
+        if partial.received < partial.fragments.len() {
+            return None;
+        }
+        let partial = self.partial.remove(&(addr, message_id))?;
+        self.bytes -= partial.reserved;
+        Some(partial.fragments.into_iter().flatten().flatten().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_all(reassembly: &mut Reassembly, addr: SocketAddr, fragments: Vec<Packet>, now: Instant) -> Option<Vec<u8>> {
+        let mut result = None;
+        for packet in fragments {
+            if let Packet::Fragment { message_id, index, count, data } = packet {
+                result = reassembly.add(addr, message_id, index, count, data, now);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_reassemble_out_of_order() {
+        let mut reassembly = Reassembly::new(Duration::from_secs(1), 0x10000);
+        let addr = "127.0.0.1:4848".parse().unwrap();
+        let data: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        let mut fragments = fragment(1, &data).unwrap();
+        assert_eq!(fragments.len(), 4);
+        fragments.reverse();
+        assert_eq!(add_all(&mut reassembly, addr, fragments, Instant::now()), Some(data));
+        assert_eq!(reassembly.bytes, 0);
+    }
+
+    #[test]
+    fn test_timeout_and_memory_cap() {
+        let mut reassembly = Reassembly::new(Duration::from_secs(1), 3000);
+        let addr = "127.0.0.1:4848".parse().unwrap();
+        let now = Instant::now();
+        let data = vec![0; 5000];
+
+        // a packet that could exceed the cap is dropped from its first fragment
+        assert_eq!(add_all(&mut reassembly, addr, fragment(1, &data).unwrap(), now), None);
+        assert_eq!(reassembly.bytes, 0);
+
+        // a partial packet is charged for all its fragments until it expires
+        assert_eq!(reassembly.add(addr, 2, 0, 2, vec![0], now), None);
+        assert_eq!(reassembly.bytes, 2 * FRAGMENT_SIZE);
+        let fragments = fragment(3, &data[..2000]).unwrap();
+        assert_eq!(add_all(&mut reassembly, addr, fragments.clone(), now), None);
+        assert_eq!(add_all(&mut reassembly, addr, fragments, now + Duration::from_secs(2)), Some(vec![0; 2000]));
+        assert_eq!(reassembly.bytes, 0);
+    }
+
+    #[test]
+    fn test_huge_count() {
+        let mut reassembly = Reassembly::new(Duration::from_secs(1), 0x100000);
+        let addr = "127.0.0.1:4848".parse().unwrap();
+        let now = Instant::now();
+
+        // tiny fragments announcing many more are charged for the whole packet
+        for message_id in 0..0x10 {
+            assert_eq!(reassembly.add(addr, message_id, 0, u16::MAX, vec![0], now), None);
+        }
+        assert!(reassembly.partial.is_empty());
+        assert_eq!(reassembly.bytes, 0);
+
+        // a peer cannot hold more than MAX_PARTIALS_PER_PEER packets at the same time
+        for message_id in 0..MAX_PARTIALS_PER_PEER as u64 + 1 {
+            assert_eq!(reassembly.add(addr, message_id, 0, 2, vec![0], now), None);
+        }
+        assert_eq!(reassembly.partial.len(), MAX_PARTIALS_PER_PEER);
+        let other = "127.0.0.1:4849".parse().unwrap();
+        assert_eq!(reassembly.add(other, 0, 0, 2, vec![0], now), None);
+        assert_eq!(reassembly.partial.len(), MAX_PARTIALS_PER_PEER + 1);
+    }
+}
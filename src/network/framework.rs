@@ -1,119 +1,1079 @@
-use std::{net::SocketAddr, sync::{Arc, RwLock}, thread};
+use std::{collections::HashMap, hash::Hash, net::{SocketAddr, UdpSocket}, sync::{Arc, RwLock}, thread, time::Instant};
 
 use anyhow::{bail, Ok};
 
-use super::{config::Config, packet::Packet, peer::Peer, routing::routing_table::RoutingTable, Network};
+use crate::id::Id;
 
-#[derive(Debug)]
-pub struct Framework 
+use super::{anti_entropy::{self, KeyRange, MERKLE_LEAF_SIZE}, application::{Application, ApplicationId, LeafSetChange}, config::Config, join::PendingJoin, packet::Packet, peer::Peer, requests::PendingRequests, storage::{Entry, StorageOperation, StorageResult}, transport::Transport, workers::WorkerPool, Network};
+
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+
+type Applications = Arc<RwLock<HashMap<ApplicationId, Arc<dyn Application>>>>;
+
+/// Runs a node of the overlay over the transport T and dispatches the messages to the registered applications.
+pub struct Framework<T: Transport = UdpSocket>
 {
-    network: Arc<RwLock<Network>>,
+    network: Arc<RwLock<Network<T>>>,
+    applications: Applications,
     running: Arc<RwLock<bool>>,
     threads: Vec<thread::JoinHandle<()>>,
 }
 
-impl Framework {
+impl<T: Transport> std::fmt::Debug for Framework<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Framework")
+            .field("network", &self.network)
+            .field("running", &self.running)
+            .field("threads", &self.threads)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Framework<UdpSocket> {
+    /// Create a framework over a UDP socket bound to the address in the config.
     pub fn new(config: Config) -> anyhow::Result<Self> {
-        Ok(Self {
-            network: Arc::new(RwLock::new(Network::new(config)?)),
+        Ok(Self::from_network(Network::new(config)?))
+    }
+}
+
+impl<T: Transport> Framework<T> {
+    /// Create a framework over the given transport, reachable at the bind address in the config.
+    pub fn with_transport(transport: T, config: Config) -> Self {
+        Self::from_network(Network::with_transport(transport, config))
+    }
+
+    fn from_network(network: Network<T>) -> Self {
+        Self {
+            network: Arc::new(RwLock::new(network)),
+            applications: Arc::new(RwLock::new(HashMap::new())),
             running: Arc::new(RwLock::new(false)),
             threads: Vec::new(),
+        }
+    }
+
+    /// Register an application that will receive the upcalls for the messages with the given id.
+    pub fn register_application<A: Application + 'static>(&self, application_id: ApplicationId, application: A) -> anyhow::Result<()> {
+        let mut applications = self.applications.write().unwrap();
+        if applications.contains_key(&application_id) {
+            bail!("Application {} is already registered", application_id);
+        }
+        applications.insert(application_id, Arc::new(application));
+        Ok(())
+    }
+
+    /// Remove the application registered with the given id.
+    pub fn unregister_application(&self, application_id: ApplicationId) -> anyhow::Result<()> {
+        match self.applications.write().unwrap().remove(&application_id) {
+            Some(_) => Ok(()),
+            None => bail!("Application {} is not registered", application_id),
+        }
+    }
+
+    /// Start a new network, this node will be the only peer.
+    /// public_addr is the address used by the other peers to reach this node.
+    pub fn bootstrap(&self, public_addr: SocketAddr) -> anyhow::Result<()> {
+        let mut network = self.network.write().unwrap();
+        if network.get_routing_table().is_some() {
+            bail!("Already part of a network");
+        }
+        network.bootstrap(public_addr)
+    }
+
+    /// Join the network through the peer at entry_addr.
+    /// The framework must be running, blocks until the join completes or times out.
+    pub fn join(&self, entry_addr: SocketAddr) -> anyhow::Result<()> {
+        // nothing would handle the responses
+        if !*self.running.read().unwrap() {
+            bail!("Framework is not running");
+        }
+        self.start_join(entry_addr)?;
+        let config = self.network.read().unwrap().config().clone();
+
+        let start = Instant::now();
+        while start.elapsed() < config.join_timeout {
+            if self.network.read().unwrap().get_routing_table().is_some() {
+                return Ok(());
+            }
+            thread::sleep(config.socket_read_timeout);
+        }
+        let mut network = self.network.write().unwrap();
+        network.set_pending_join(None);
+        // the last response may have arrived while the lock was released
+        match network.get_routing_table() {
+            Some(_) => Ok(()),
+            None => bail!("Join timed out"),
+        }
+    }
+
+    /// Send the join request to the peer at entry_addr without waiting for the responses,
+    /// the join completes when local_peer returns Some.
+    /// The framework does not need to be running, the responses can be handled by poll
+    /// and the join request is sent again by tick if it was lost.
+    pub fn start_join(&self, entry_addr: SocketAddr) -> anyhow::Result<()> {
+        let mut network = self.network.write().unwrap();
+        if network.get_routing_table().is_some() {
+            bail!("Already part of a network");
+        }
+        let now = network.now();
+        network.set_pending_join(Some(PendingJoin::new(entry_addr, now)));
+        network.send(Packet::JoinRequest, entry_addr)
+    }
+
+    /// Get the peer of this node as the other peers see it, None if it is not part of a network.
+    pub fn local_peer(&self) -> Option<Peer> {
+        self.network.read().unwrap().local_peer()
+    }
+
+    /// Get the peer the messages with the given key are forwarded to, None if this node is the closest to the key.
+    pub fn next_hop(&self, key: &Id) -> anyhow::Result<Option<Peer>> {
+        self.network.read().unwrap().route(key).map(|peer| peer.copied())
+    }
+
+    /// Join the network through the entry address in the config.
+    pub fn join_entry(&self) -> anyhow::Result<()> {
+        let entry_addr = self.network.read().unwrap().config().entry_addr;
+        self.join(entry_addr)
+    }
+
+    /// Send a message to the instance of the application running on the peer that is numerically closest to the key.
+    pub fn send(&self, application_id: ApplicationId, key: Id, payload: Vec<u8>) -> anyhow::Result<()> {
+        Self::route_message(self.network.clone(), self.applications.clone(), application_id, key, payload, None)
+    }
+
+    /// Send a message like send, but wait for the peer closest to the key to acknowledge it.
+    /// The message is retransmitted if the acknowledgement does not arrive in time, doubling the wait each time.
+    /// Fails if the message is not acknowledged after max_retransmissions retransmissions.
+    pub fn send_reliable(&self, application_id: ApplicationId, key: Id, payload: Vec<u8>) -> anyhow::Result<()> {
+        let (message_id, receiver, config) = {
+            let mut network = self.network.write().unwrap();
+            let (message_id, receiver) = network.get_acks_mut().register();
+            (message_id, receiver, network.config().clone())
+        };
+        let mut timeout = config.retransmission_timeout;
+        for _ in 0..=config.max_retransmissions {
+            if let Err(e) = Self::route_message(self.network.clone(), self.applications.clone(), application_id, key, payload.clone(), Some((message_id, None))) {
+                self.network.write().unwrap().get_acks_mut().cancel(message_id);
+                return Err(e);
+            }
+            if receiver.recv_timeout(timeout).is_ok() {
+                return Ok(());
+            }
+            timeout = timeout.saturating_mul(2);
+        }
+        self.network.write().unwrap().get_acks_mut().cancel(message_id);
+        bail!("The message was not acknowledged after {} retransmissions", config.max_retransmissions)
+    }
+
+    /// Send a message to the instance of the application running on every peer, this one included.
+    pub fn broadcast(&self, application_id: ApplicationId, key: Id, payload: Vec<u8>) -> anyhow::Result<()> {
+        Self::forward_broadcast(self.network.clone(), self.applications.clone(), application_id, 0, key, payload)
+    }
+
+    /// Send a request to the instance of the application running on the peer that is numerically closest to the key
+    /// and wait for its response.
+    pub fn request(&self, application_id: ApplicationId, key: Id, payload: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let response = self.await_response(Network::get_rpc_requests_mut, |request_id| {
+            Self::route_request(self.network.clone(), self.applications.clone(), request_id, None, application_id, key, payload)
+        })?;
+        match response {
+            Some(response) => Ok(response),
+            None => bail!("Application {} does not handle requests on the peer responsible for the key", application_id),
+        }
+    }
+
+    /// Find the peer numerically closest to the key.
+    /// Returns the peer and the number of hops the lookup took to reach it.
+    pub fn lookup(&self, key: Id) -> anyhow::Result<(Peer, u8)> {
+        self.await_response(Network::get_lookups_mut, |request_id| Self::route_lookup(&self.network, request_id, None, key, 0))
+    }
+
+    /// Register a request in the requests returned by pending, send it with send and wait for its response.
+    /// The request is cancelled if it cannot be sent or if the response does not arrive within the request timeout.
+    fn await_response<R>(&self, pending: impl Fn(&mut Network<T>) -> &mut PendingRequests<R>, send: impl FnOnce(u64) -> anyhow::Result<()>) -> anyhow::Result<R> {
+        let (request_id, receiver, timeout) = {
+            let mut network = self.network.write().unwrap();
+            let timeout = network.config().request_timeout;
+            let (request_id, receiver) = pending(&mut network).register();
+            (request_id, receiver, timeout)
+        };
+        if let Err(e) = send(request_id) {
+            pending(&mut self.network.write().unwrap()).cancel(request_id);
+            return Err(e);
+        }
+        match receiver.recv_timeout(timeout) {
+            std::result::Result::Ok(response) => Ok(response),
+            Err(_) => {
+                pending(&mut self.network.write().unwrap()).cancel(request_id);
+                bail!("The peer responsible for the key did not answer in time")
+            },
+        }
+    }
+
+    /// Store the value in the key-value store of the peer numerically closest to the key.
+    pub fn put(&self, key: Id, value: Vec<u8>) -> anyhow::Result<()> {
+        match self.storage_request(key, StorageOperation::Put { value })? {
+            StorageResult::Stored => Ok(()),
+            result => bail!("Unexpected storage result: {:?}", result),
+        }
+    }
+
+    /// Get the value from the key-value store of the peer numerically closest to the key.
+    /// Returns None if the key is not stored.
+    pub fn get(&self, key: Id) -> anyhow::Result<Option<Vec<u8>>> {
+        match self.storage_request(key, StorageOperation::Get)? {
+            StorageResult::Value(value) => Ok(value),
+            result => bail!("Unexpected storage result: {:?}", result),
+        }
+    }
+
+    /// Delete the key from the key-value store of the peer numerically closest to the key.
+    pub fn delete(&self, key: Id) -> anyhow::Result<()> {
+        match self.storage_request(key, StorageOperation::Delete)? {
+            StorageResult::Deleted => Ok(()),
+            result => bail!("Unexpected storage result: {:?}", result),
+        }
+    }
+
+    /// Subscribe the application to the topic, it receives the messages published on it in deliver,
+    /// with the id of the topic as the key.
+    pub fn subscribe<K: Hash>(&self, application_id: ApplicationId, topic: K) -> anyhow::Result<()> {
+        if !self.applications.read().unwrap().contains_key(&application_id) {
+            bail!("Application {} is not registered", application_id);
+        }
+        let topic = Id::from_key(topic);
+        let mut network = self.network.write().unwrap();
+        if network.get_routing_table().is_none() {
+            bail!("Not part of a network");
+        }
+        if network.get_groups_mut().subscribe(topic, application_id) {
+            Self::join_group(&mut network, topic)?;
+        }
+        Ok(())
+    }
+
+    /// Unsubscribe the application from the topic.
+    pub fn unsubscribe<K: Hash>(&self, application_id: ApplicationId, topic: K) -> anyhow::Result<()> {
+        let topic = Id::from_key(topic);
+        let mut network = self.network.write().unwrap();
+        if !network.get_groups().members(&topic).contains(&application_id) {
+            bail!("Application {} is not subscribed to the topic", application_id);
+        }
+        if let Some(parent) = network.get_groups_mut().unsubscribe(&topic, application_id) {
+            network.send(Packet::Unsubscribe { topic }, parent.addr())?;
+        }
+        Ok(())
+    }
+
+    /// Publish the payload to every application subscribed to the topic, on any peer.
+    pub fn publish<K: Hash>(&self, topic: K, payload: Vec<u8>) -> anyhow::Result<()> {
+        Self::route_publish(self.network.clone(), self.applications.clone(), Id::from_key(topic), payload)
+    }
+
+    /// Send the payload to a single application subscribed to the group, the first one met on the way to the root of the group.
+    /// Since the routing prefers peers with lower round trip times, the member is likely to be physically close.
+    pub fn anycast<K: Hash>(&self, group: K, payload: Vec<u8>) -> anyhow::Result<()> {
+        Self::route_anycast(self.network.clone(), self.applications.clone(), Id::from_key(group), payload, Vec::new())
+    }
+
+    /// Route the operation to the peer numerically closest to the key and wait for the result.
+    fn storage_request(&self, key: Id, operation: StorageOperation) -> anyhow::Result<StorageResult> {
+        self.await_response(Network::get_storage_requests_mut, |request_id| {
+            Self::route_storage(&self.network, Packet::Storage { request_id, origin: None, key, operation }, None)
         })
     }
 
-    fn handle_packet(network: Arc<RwLock<Network>>, packet: Packet, addr: SocketAddr) -> anyhow::Result<()> {
-        match packet {
-            Packet::JoinRequest => 
-            {
-                let peer = Peer::new(addr);
-                let packet = Packet::PeerIsJoining { applicant: peer, hop_count: 0 };
-                let network = network.read().unwrap();
-                let next_hop = network.route(&peer.id())?;
-                if let Some(next_hop) = next_hop {
-                    network.send(packet, next_hop.addr())?;
+    /// Forward a Storage packet to the next hop or execute the operation if this node is the closest one.
+    /// from is the address the packet was received from, None if the operation was requested by this node.
+    fn route_storage(network: &Arc<RwLock<Network<T>>>, packet: Packet, from: Option<SocketAddr>) -> anyhow::Result<()> {
+        let (request_id, origin, key, operation) = match packet {
+            Packet::Storage { request_id, origin, key, operation } => (request_id, origin.or(from), key, operation),
+            _ => bail!("Not a storage packet"),
+        };
+        let mut network = network.write().unwrap();
+        match network.route(&key)?.copied() {
+            Some(next_hop) => {
+                network.send(Packet::Storage { request_id, origin, key, operation }, next_hop.addr())?;
+            },
+            None => {
+                let replicas = network.replicas(&key);
+                // a deleted key has a tombstone, a key without entry may be missing only here
+                let missing = network.get_storage().entry(&key).is_none();
+                let result = Self::store(&mut network, key, operation)?;
+                match (result, replicas.split_first()) {
+                    // for example if this node has just become the closest to the key, or a packet was lost
+                    (StorageResult::Value(None), Some((replica, remaining))) if missing => {
+                        let remaining = remaining.iter().map(|replica| replica.addr()).collect();
+                        network.send(Packet::ReplicaGet { request_id, origin, key, remaining }, replica.addr())?;
+                    },
+                    (result, _) => match origin {
+                        Some(origin) => network.send(Packet::StorageResult { request_id, result }, origin)?,
+                        None => {
+                            network.get_storage_requests_mut().complete(request_id, result);
+                        },
+                    },
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Execute the operation on a key this node is the closest to and send the changes to the replicas.
+    fn store(network: &mut Network<T>, key: Id, operation: StorageOperation) -> anyhow::Result<StorageResult> {
+        let is_write = operation != StorageOperation::Get;
+        let now = network.system_time();
+        let result = network.get_storage_mut().execute(key, operation, now);
+        if is_write {
+            Self::replicate(network, key)?;
+        }
+        Ok(result)
+    }
+
+    /// Keep an entry handed over for a key this node is the closest to, and send it to the replicas if it is newer.
+    fn merge(network: &mut Network<T>, key: Id, entry: Entry) -> anyhow::Result<()> {
+        let now = network.system_time();
+        if network.get_storage_mut().merge(key, entry, now) {
+            Self::replicate(network, key)?;
+        }
+        Ok(())
+    }
+
+    /// Send the entry of the key to its replicas.
+    fn replicate(network: &Network<T>, key: Id) -> anyhow::Result<()> {
+        if let Some(entry) = network.get_storage().entry(&key) {
+            for replica in network.replicas(&key) {
+                network.send(Packet::Replicate { key, entry: entry.clone() }, replica.addr())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the stored keys where they belong after a change in the leaf set.
+    /// The keys this node is the closest to are sent to the replicas,
+    /// the keys that are now closer to a peer that has just joined are handed off to it,
+    /// the keys this node should no longer replicate are handed off to the closest node and deleted.
+    fn rebalance(network: &Arc<RwLock<Network<T>>>, changes: &[LeafSetChange]) -> anyhow::Result<()> {
+        let mut network = network.write().unwrap();
+        let joined: Vec<Peer> = changes.iter().filter_map(|change| match change {
+            LeafSetChange::Joined(peer) => Some(*peer),
+            LeafSetChange::Left(_) => None,
+        }).collect();
+        let mut moved = Vec::new();
+        for (key, entry) in network.get_storage().iter() {
+            match network.route(key)? {
+                None => Self::replicate(&network, *key)?,
+                Some(next_hop) => {
+                    let is_replica = network.is_replica(key);
+                    if !is_replica || joined.contains(next_hop) {
+                        network.send(Packet::Handoff { key: *key, entry: entry.clone() }, next_hop.addr())?;
+                    }
+                    if !is_replica {
+                        moved.push(*key);
+                    }
+                },
+            }
+        }
+        for key in moved {
+            network.get_storage_mut().remove(&key);
+        }
+        Ok(())
+    }
+
+    /// Start an anti-entropy round, comparing the keys this node is the closest to with the ones on its replicas.
+    /// The expired tombstones are purged first, the replicas purge theirs in their own rounds.
+    fn anti_entropy(network: &Arc<RwLock<Network<T>>>) -> anyhow::Result<()> {
+        let mut network = network.write().unwrap();
+        let (tombstone_timeout, now) = (network.config().tombstone_timeout, network.system_time());
+        network.get_storage_mut().purge(tombstone_timeout, now);
+        let (range, node_id) = match (network.owned_range(), network.get_routing_table()) {
+            (Some(range), Some(routing_table)) => (range, routing_table.node_id()),
+            _ => return Ok(()),
+        };
+        let hash = anti_entropy::merkle_hash(&anti_entropy::digests(network.get_storage(), &range), &range);
+        for replica in network.replicas(&node_id) {
+            network.send(Packet::SyncRequest { range, hash }, replica.addr())?;
+        }
+        Ok(())
+    }
+
+    /// Reconcile the entries of a replica with the digests of its keys in the range, in both directions.
+    /// The entries this node stores in a newer version are sent to the replica,
+    /// the ones the replica stores in a newer version, or that this node is missing, are pulled from it,
+    /// since a packet may have been lost on the way to either of them.
+    fn repair_replica(network: &Network<T>, range: &KeyRange, digests: Vec<(Id, u64)>, replica: SocketAddr) -> anyhow::Result<()> {
+        let own = anti_entropy::digests(network.get_storage(), range);
+        let version = |digests: &[(Id, u64)], key: &Id| digests.iter().find(|(other, _)| other == key).map(|(_, version)| *version);
+        for (key, own_version) in own.iter() {
+            if version(&digests, key).is_none_or(|version| version < *own_version) && network.route(key)?.is_none() {
+                if let Some(entry) = network.get_storage().entry(key) {
+                    network.send(Packet::Replicate { key: *key, entry: entry.clone() }, replica)?;
+                }
+            }
+        }
+        let mut keys = Vec::new();
+        for (key, replica_version) in digests.iter() {
+            if version(&own, key).is_none_or(|version| version < *replica_version) && network.route(key)?.is_none() {
+                keys.push(*key);
+            }
+        }
+        if !keys.is_empty() {
+            network.send(Packet::SyncPull { keys }, replica)?;
+        }
+        Ok(())
+    }
+
+    /// Notify every application of a change in the leaf set and update the replicas of the stored keys.
+    fn notify_leaf_set_change(network: &Arc<RwLock<Network<T>>>, applications: &Applications, changes: Vec<LeafSetChange>) {
+        if changes.is_empty() {
+            return;
+        }
+        if let Err(_e) = Self::rebalance(network, &changes)
+        {
+            // TODO: maybe log the error
+        }
+        let applications: Vec<_> = applications.read().unwrap().values().cloned().collect();
+        for change in changes {
+            for application in applications.iter() {
+                application.update(change);
+            }
+        }
+    }
+
+    /// Join the multicast tree of the topic through the next hop towards its root,
+    /// or become the root if this node is the closest to the topic.
+    fn join_group(network: &mut Network<T>, topic: Id) -> anyhow::Result<()> {
+        let next_hop = network.route(&topic)?.copied();
+        network.get_groups_mut().set_parent(&topic, next_hop);
+        if let Some(next_hop) = next_hop {
+            network.send(Packet::Subscribe { topic }, next_hop.addr())?;
+        }
+        Ok(())
+    }
+
+    /// Join again the multicast trees where this node has no parent,
+    /// because its parent failed or because a peer closer to the topic than this root has joined.
+    fn rejoin_groups(network: &mut Network<T>) -> anyhow::Result<()> {
+        for topic in network.get_groups().orphans() {
+            Self::join_group(network, topic)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a peer that failed or left from the multicast trees and repair them.
+    fn remove_group_peer(network: &mut Network<T>, peer: &Peer) -> anyhow::Result<()> {
+        for (topic, parent) in network.get_groups_mut().remove_peer(peer) {
+            network.send(Packet::Unsubscribe { topic }, parent.addr())?;
+        }
+        Self::rejoin_groups(network)
+    }
+
+    /// Forward a published message towards the root of the topic, or multicast it if this node is the root.
+    fn route_publish(network: Arc<RwLock<Network<T>>>, applications: Applications, topic: Id, payload: Vec<u8>) -> anyhow::Result<()> {
+        let next_hop = network.read().unwrap().route(&topic)?.copied();
+        match next_hop {
+            Some(next_hop) => network.read().unwrap().send(Packet::Publish { topic, payload }, next_hop.addr()),
+            None => {
+                let message_id = network.read().unwrap().next_message_id();
+                Self::multicast(network, applications, topic, message_id, payload)
+            },
+        }
+    }
+
+    /// Send the message to the children in the multicast tree of the topic and deliver it to the local members.
+    /// A message this node already multicast is dropped.
+    fn multicast(network: Arc<RwLock<Network<T>>>, applications: Applications, topic: Id, message_id: u64, payload: Vec<u8>) -> anyhow::Result<()> {
+        let members = {
+            let mut network = network.write().unwrap();
+            if !network.get_groups_mut().multicast(&topic, message_id) {
+                return Ok(());
+            }
+            for child in network.get_groups().children(&topic) {
+                network.send(Packet::Multicast { topic, message_id, payload: payload.clone() }, child.addr())?;
+            }
+            network.get_groups().members(&topic)
+        };
+        let members: Vec<_> = {
+            let applications = applications.read().unwrap();
+            members.iter().filter_map(|application_id| applications.get(application_id).cloned()).collect()
+        };
+        for member in members {
+            member.deliver(topic, payload.clone());
+        }
+        Ok(())
+    }
+
+    /// Deliver the anycast message if a local application is a member of the group,
+    /// otherwise search the multicast tree depth first: first the children that were not visited, then the parent.
+    /// A node that is not part of the tree forwards the message towards the root of the group.
+    /// The message is dropped when the whole tree has been searched.
+    fn route_anycast(network: Arc<RwLock<Network<T>>>, applications: Applications, topic: Id, payload: Vec<u8>, mut visited: Vec<Id>) -> anyhow::Result<()> {
+        let members = network.read().unwrap().get_groups().members(&topic);
+        let member = {
+            let applications = applications.read().unwrap();
+            members.iter().find_map(|application_id| applications.get(application_id).cloned())
+        };
+        if let Some(member) = member {
+            member.deliver(topic, payload);
+            return Ok(());
+        }
+        let network = network.read().unwrap();
+        let groups = network.get_groups();
+        let next_hop = match groups.contains(&topic) {
+            true => {
+                let node_id = match network.get_routing_table() {
+                    Some(routing_table) => routing_table.node_id(),
+                    None => bail!("Routing table is not initialized"),
+                };
+                if !visited.contains(&node_id) {
+                    visited.push(node_id);
+                }
+                groups.children(&topic).into_iter()
+                    .find(|child| !visited.contains(&child.id()))
+                    .or(groups.parent(&topic))
+            },
+            false => network.route(&topic)?.copied(),
+        };
+        if let Some(next_hop) = next_hop {
+            network.send(Packet::Anycast { topic, payload, visited }, next_hop.addr())?;
+        }
+        Ok(())
+    }
+
+    /// Forward the broadcast to the peers responsible for the blocks of the id space from the given level,
+    /// then deliver it to the application, if registered.
+    fn forward_broadcast(network: Arc<RwLock<Network<T>>>, applications: Applications, application_id: ApplicationId, level: u8, key: Id, payload: Vec<u8>) -> anyhow::Result<()> {
+        {
+            let network = network.read().unwrap();
+            let targets = match network.get_routing_table() {
+                Some(routing_table) => routing_table.broadcast_targets(level as usize),
+                None => bail!("Routing table is not initialized"),
+            };
+            for (peer, level) in targets {
+                network.send(Packet::Broadcast { application_id, level: level as u8, key, payload: payload.clone() }, peer.addr())?;
+            }
+        }
+        let application = applications.read().unwrap().get(&application_id).cloned();
+        if let Some(application) = application {
+            application.deliver(key, payload);
+        }
+        Ok(())
+    }
+
+    /// Forward the message to the next hop or deliver it to the application if this node is the closest one.
+    /// If no application is registered with the given id the message is forwarded unchanged,
+    /// or dropped if this node is the closest one.
+    /// reliable holds the id and the origin of a reliable message, it is acknowledged once delivered
+    /// and delivered only once even if it is retransmitted.
+    fn route_message(network: Arc<RwLock<Network<T>>>, applications: Applications, application_id: ApplicationId, key: Id, payload: Vec<u8>, reliable: Option<(u64, Option<SocketAddr>)>) -> anyhow::Result<()> {
+        // the next_hop variable trick is to unlock the network before calling the application
+        let next_hop = network.read().unwrap().route(&key)?.copied();
+        let application = applications.read().unwrap().get(&application_id).cloned();
+        match next_hop {
+            Some(next_hop) => {
+                let payload = match application {
+                    Some(application) => application.forward(key, payload, &next_hop),
+                    None => Some(payload),
+                };
+                if let Some(payload) = payload {
+                    let packet = match reliable {
+                        Some((message_id, origin)) => Packet::ReliableMessage { message_id, origin, application_id, key, payload },
+                        None => Packet::Message { application_id, key, payload },
+                    };
+                    network.read().unwrap().send(packet, next_hop.addr())?;
+                }
+            },
+            None => {
+                let application = match application {
+                    Some(application) => application,
+                    None => bail!("No application registered with id {}", application_id),
+                };
+                let first_delivery = match reliable {
+                    Some((message_id, Some(origin))) => {
+                        let mut network = network.write().unwrap();
+                        let now = network.now();
+                        network.get_deliveries_mut().deliver(origin, message_id, now)
+                    },
+                    _ => true,
+                };
+                if first_delivery {
+                    application.deliver(key, payload);
                 }
-                else
-                {
-                    let leaves = network.get_routing_table().unwrap().leaves_to_vec();
-                    let routing_table_row = network.get_routing_table().unwrap().row(0);
-                    let packet = Packet::JoinResponse { applicant_id: peer.id(), routing_table_row, leaves, hop_count: 0 };
-                    network.send(packet, addr)?;
+                match reliable {
+                    Some((message_id, Some(origin))) => network.read().unwrap().send(Packet::Ack { message_id }, origin)?,
+                    Some((message_id, None)) => {
+                        network.write().unwrap().get_acks_mut().complete(message_id, ());
+                    },
+                    None => {},
                 }
             },
+        }
+        Ok(())
+    }
+
+    /// Forward a request to the next hop or let the application answer it if this node is the closest one,
+    /// the response is sent directly to the origin, None if the request was sent by this node.
+    fn route_request(network: Arc<RwLock<Network<T>>>, applications: Applications, request_id: u64, origin: Option<Peer>, application_id: ApplicationId, key: Id, payload: Vec<u8>) -> anyhow::Result<()> {
+        let (next_hop, local_peer) = {
+            let network = network.read().unwrap();
+            (network.route(&key)?.copied(), network.local_peer())
+        };
+        if let Some(next_hop) = next_hop {
+            let packet = Packet::Request { request_id, origin, application_id, key, payload };
+            return network.read().unwrap().send(packet, next_hop.addr());
+        }
+        let application = applications.read().unwrap().get(&application_id).cloned();
+        let response = match (application, origin.or(local_peer)) {
+            (Some(application), Some(requester)) => application.request(key, payload, &requester),
+            _ => None,
+        };
+        match origin {
+            Some(origin) => network.read().unwrap().send(Packet::Response { request_id, payload: response }, origin.addr())?,
+            None => {
+                network.write().unwrap().get_rpc_requests_mut().complete(request_id, response);
+            },
+        }
+        Ok(())
+    }
+
+    /// Forward a lookup to the next hop or answer it if this node is the closest one,
+    /// origin is None if the lookup was started by this node.
+    fn route_lookup(network: &Arc<RwLock<Network<T>>>, request_id: u64, origin: Option<SocketAddr>, key: Id, hop_count: u8) -> anyhow::Result<()> {
+        let mut network = network.write().unwrap();
+        match (network.route(&key)?.copied(), origin) {
+            (Some(next_hop), origin) => {
+                let hop_count = hop_count.saturating_add(1);
+                network.send(Packet::Lookup { request_id, origin, key, hop_count }, next_hop.addr())?;
+            },
+            (None, Some(origin)) => network.send(Packet::LookupResult { request_id, hop_count }, origin)?,
+            (None, None) => {
+                let local_peer = match network.local_peer() {
+                    Some(local_peer) => local_peer,
+                    None => bail!("Routing table is not initialized"),
+                };
+                network.get_lookups_mut().complete(request_id, (local_peer, hop_count));
+            },
+        }
+        Ok(())
+    }
+
+    fn handle_packet(network: Arc<RwLock<Network<T>>>, applications: Applications, packet: Packet, addr: SocketAddr) -> anyhow::Result<()> {
+        match packet {
+            Packet::JoinRequest => {
+                // the entry point is the first hop of the join request
+                let packet = Packet::PeerIsJoining { applicant: Peer::new(addr), hop_count: 0 };
+                Self::handle_packet(network, applications, packet, addr)?;
+            },
             Packet::PeerIsJoining { applicant, hop_count } => {
                 let network = network.read().unwrap();
-                let next_hop = network.route(&applicant.id())?;
+                let routing_table = match network.get_routing_table() {
+                    Some(routing_table) => routing_table,
+                    None => bail!("Routing table is not initialized"),
+                };
+                let next_hop = routing_table.route(&applicant.id()).copied();
+                let routing_table_row = routing_table.row(hop_count as usize);
+                let leaves = routing_table.leaves_to_vec();
+                let neighbors = routing_table.neighborhood_set().to_vec();
+                let is_last = next_hop.is_none();
+                let packet = Packet::JoinResponse { applicant, routing_table_row, leaves, neighbors, hop_count, is_last };
+                network.send(packet, applicant.addr())?;
                 if let Some(next_hop) = next_hop {
                     let next_hop_count = match hop_count.checked_add(1) {
                         Some(count) => count,
                         None => bail!("Hop count overflow"),
                     };
-                    let packet = Packet::PeerIsJoining { applicant, hop_count: next_hop_count};
+                    let packet = Packet::PeerIsJoining { applicant, hop_count: next_hop_count };
                     network.send(packet, next_hop.addr())?;
-                    let routing_table_row = network.get_routing_table().unwrap().row(hop_count as usize);
-                    let leaves = network.get_routing_table().unwrap().leaves_to_vec();
-                    let packet = Packet::JoinResponse { applicant_id: applicant.id(), routing_table_row, leaves, hop_count };
-                    network.send(packet, addr)?;
-                }
-                else
-                {
-                    let leaves = network.get_routing_table().unwrap().leaves_to_vec();
-                    let routing_table_row = network.get_routing_table().unwrap().row(hop_count as usize);
-                    let packet = Packet::JoinResponse { applicant_id: applicant.id(), routing_table_row, leaves, hop_count };
-                    network.send(packet, addr)?;
                 }
             },
-            Packet::JoinResponse { applicant_id, routing_table_row, leaves, hop_count } => {
-                let mut network = network.write().unwrap();
-                if network.get_routing_table().is_none() {
-                    let mut routing_table = RoutingTable::empty(applicant_id);
-                    routing_table.set_row(routing_table_row, hop_count as usize);
-                    routing_table.add_leaves(leaves);
-                    network.set_routing_table(routing_table);
+            Packet::JoinResponse { applicant, routing_table_row, leaves, neighbors, hop_count, is_last } => {
+                let joined = {
+                    let mut network = network.write().unwrap();
+                    match network.get_pending_join_mut() {
+                        Some(pending_join) => {
+                            pending_join.add_response(Peer::new(addr), applicant, routing_table_row, leaves, neighbors, hop_count, is_last);
+                            if pending_join.is_complete() {
+                                network.take_pending_join().and_then(|pending_join| pending_join.into_routing_table())
+                            }
+                            else
+                            {
+                                None
+                            }
+                        },
+                        None => None,
+                    }
+                };
+                if let Some((local_peer, routing_table, changes)) = joined {
+                    let peers = routing_table.peers();
+                    let leaves = routing_table.leaves_to_vec();
+                    {
+                        let mut network = network.write().unwrap();
+                        network.set_public_addr(local_peer.addr());
+                        network.set_routing_table(routing_table);
+                    }
+                    Self::notify_leaf_set_change(&network, &applications, changes);
+                    let network = network.read().unwrap();
+                    for peer in peers {
+                        network.send(Packet::Announce { leaves: leaves.clone() }, peer.addr())?;
+                    }
                 }
             },
+            Packet::Announce { leaves } => {
+                let changes = {
+                    let mut network = network.write().unwrap();
+                    if network.get_routing_table().is_none() {
+                        bail!("Routing table is not initialized");
+                    }
+                    // the peer has just joined, even if it was declared dead before
+                    let peer = Peer::new(addr);
+                    network.get_liveness_mut().revive(&peer.id());
+                    let mut peers = vec![peer];
+                    peers.extend(leaves);
+                    network.add_peers(peers)
+                };
+                Self::notify_leaf_set_change(&network, &applications, changes);
+            },
             Packet::Ping { nonce } => {
                 let packet = Packet::Pong { nonce };
                 let network = network.read().unwrap();
                 network.send(packet, addr)?;
             },
-            Packet::Pong { nonce } => todo!(),
-            Packet::Message { key, payload } => {
-                // the message_is_for_me variable trick is to unlock the network before handling the message
-                let message_is_for_me = {
-                    let network = network.read().unwrap();
-                    let next_hop = network.route(&key)?;
-                    if let Some(next_hop) = next_hop {
-                        let packet = Packet::Message { key, payload };
-                        network.send(packet, next_hop.addr())?;
-                        false
+            Packet::Pong { nonce } => {
+                let mut network = network.write().unwrap();
+                let now = network.now();
+                let pong = network.get_liveness_mut().pong(nonce, now);
+                if let (Some((peer, rtt)), Some(routing_table)) = (pong, network.get_routing_table_mut()) {
+                    routing_table.update_rtt(peer, rtt);
+                }
+            },
+            Packet::LeafSetRequest => {
+                let network = network.read().unwrap();
+                let leaves = match network.get_routing_table() {
+                    Some(routing_table) => routing_table.leaves_to_vec(),
+                    None => bail!("Routing table is not initialized"),
+                };
+                network.send(Packet::LeafSetResponse { leaves }, addr)?;
+            },
+            Packet::LeafSetResponse { leaves } => {
+                let changes = {
+                    let mut network = network.write().unwrap();
+                    if network.get_routing_table().is_none() {
+                        bail!("Routing table is not initialized");
                     }
-                    else
-                    {
-                        true
+                    network.add_peers(leaves)
+                };
+                Self::notify_leaf_set_change(&network, &applications, changes);
+            },
+            Packet::RoutingEntryRequest { row, column } => {
+                let network = network.read().unwrap();
+                let peer = match network.get_routing_table() {
+                    Some(routing_table) => routing_table.entry(row as usize, column),
+                    None => bail!("Routing table is not initialized"),
+                };
+                // an entry missing its probes may have failed, advertising it would bring it back to the peers
+                // that already removed it, once their dead_peer_timeout has expired
+                let peer = peer.filter(|peer| !network.get_liveness().is_suspected(&peer.id()));
+                network.send(Packet::RoutingEntryResponse { row, column, peer }, addr)?;
+            },
+            Packet::RoutingEntryResponse { row, column, peer } => {
+                let mut network = network.write().unwrap();
+                let row = row as usize;
+                if !network.get_repairs_mut().is_pending(row, column) {
+                    return Ok(());
+                }
+                let peer = peer.filter(|peer| !network.get_liveness().is_dead(&peer.id()));
+                let replaced = match (network.get_routing_table_mut(), peer) {
+                    (Some(routing_table), Some(peer)) if routing_table.slot(&peer.id()) == Some((row, column)) => {
+                        routing_table.insert(peer)
+                    },
+                    _ => false,
+                };
+                if replaced {
+                    network.get_repairs_mut().complete(row, column);
+                }
+                else if let Some(contact) = network.get_repairs_mut().next_contact(row, column) {
+                    network.send(Packet::RoutingEntryRequest { row: row as u8, column }, contact.addr())?;
+                }
+            },
+            Packet::Leaving { leaves } => {
+                let changes = {
+                    let mut network = network.write().unwrap();
+                    if network.get_routing_table().is_none() {
+                        bail!("Routing table is not initialized");
                     }
+                    // the leaves of the peer that is leaving replace it, no need to ask for a leaf set
+                    let peer = Peer::new(addr);
+                    let (mut changes, _) = network.remove_peer(&peer);
+                    changes.extend(network.add_peers(leaves));
+                    Self::remove_group_peer(&mut network, &peer)?;
+                    changes
                 };
-                if message_is_for_me {
-                    todo!("Handle message")
+                Self::notify_leaf_set_change(&network, &applications, changes);
+            },
+            Packet::Storage { .. } => {
+                Self::route_storage(&network, packet, Some(addr))?;
+            },
+            Packet::Replicate { key, entry } => {
+                let mut network = network.write().unwrap();
+                let now = network.system_time();
+                network.get_storage_mut().merge(key, entry, now);
+            },
+            Packet::Handoff { key, entry } => {
+                let mut network = network.write().unwrap();
+                match network.route(&key)?.copied() {
+                    Some(next_hop) => network.send(Packet::Handoff { key, entry }, next_hop.addr())?,
+                    None => Self::merge(&mut network, key, entry)?,
+                }
+            },
+            Packet::SyncRequest { range, hash } => {
+                let network = network.read().unwrap();
+                let digests = anti_entropy::digests(network.get_storage(), &range);
+                if anti_entropy::merkle_hash(&digests, &range) != hash {
+                    let digests = match range.split() {
+                        Some(_) if digests.len() > MERKLE_LEAF_SIZE => None,
+                        _ => Some(digests),
+                    };
+                    network.send(Packet::SyncResponse { range, digests }, addr)?;
+                }
+            },
+            Packet::SyncResponse { range, digests } => {
+                let network = network.read().unwrap();
+                match digests {
+                    Some(digests) => Self::repair_replica(&network, &range, digests, addr)?,
+                    None => {
+                        for half in range.split().into_iter().flat_map(|(left, right)| [left, right]) {
+                            let hash = anti_entropy::merkle_hash(&anti_entropy::digests(network.get_storage(), &half), &half);
+                            network.send(Packet::SyncRequest { range: half, hash }, addr)?;
+                        }
+                    },
+                }
+            },
+            Packet::SyncPull { keys } => {
+                let network = network.read().unwrap();
+                for key in keys {
+                    if let Some(entry) = network.get_storage().entry(&key) {
+                        network.send(Packet::Handoff { key, entry: entry.clone() }, addr)?;
+                    }
+                }
+            },
+            Packet::ReplicaGet { request_id, origin, key, mut remaining } => {
+                let network = network.read().unwrap();
+                let origin = origin.unwrap_or(addr);
+                match network.get_storage().entry(&key) {
+                    // a tombstone answers too, the key was deleted
+                    Some(entry) => {
+                        let result = StorageResult::Value(entry.value.clone());
+                        network.send(Packet::StorageResult { request_id, result }, origin)?;
+                    },
+                    None if !remaining.is_empty() => {
+                        let next = remaining.remove(0);
+                        network.send(Packet::ReplicaGet { request_id, origin: Some(origin), key, remaining }, next)?;
+                    },
+                    None => {
+                        network.send(Packet::StorageResult { request_id, result: StorageResult::Value(None) }, origin)?;
+                    },
+                }
+            },
+            Packet::StorageResult { request_id, result } => {
+                network.write().unwrap().get_storage_requests_mut().complete(request_id, result);
+            },
+            Packet::Subscribe { topic } => {
+                let mut network = network.write().unwrap();
+                if network.get_routing_table().is_none() {
+                    bail!("Routing table is not initialized");
                 }
+                if network.get_groups_mut().add_child(topic, Peer::new(addr)) {
+                    Self::join_group(&mut network, topic)?;
+                }
+            },
+            Packet::Unsubscribe { topic } => {
+                let mut network = network.write().unwrap();
+                if let Some(parent) = network.get_groups_mut().remove_child(&topic, &Peer::new(addr)) {
+                    network.send(Packet::Unsubscribe { topic }, parent.addr())?;
+                }
+            },
+            Packet::Publish { topic, payload } => {
+                Self::route_publish(network, applications, topic, payload)?;
+            },
+            Packet::Multicast { topic, message_id, payload } => {
+                Self::multicast(network, applications, topic, message_id, payload)?;
+            },
+            Packet::Broadcast { application_id, level, key, payload } => {
+                Self::forward_broadcast(network, applications, application_id, level, key, payload)?;
+            },
+            Packet::Anycast { topic, payload, visited } => {
+                Self::route_anycast(network, applications, topic, payload, visited)?;
+            },
+            Packet::Request { request_id, origin, application_id, key, payload } => {
+                let origin = origin.unwrap_or(Peer::new(addr));
+                Self::route_request(network, applications, request_id, Some(origin), application_id, key, payload)?;
+            },
+            Packet::Response { request_id, payload } => {
+                network.write().unwrap().get_rpc_requests_mut().complete(request_id, payload);
+            },
+            Packet::Lookup { request_id, origin, key, hop_count } => {
+                Self::route_lookup(&network, request_id, Some(origin.unwrap_or(addr)), key, hop_count)?;
+            },
+            Packet::LookupResult { request_id, hop_count } => {
+                network.write().unwrap().get_lookups_mut().complete(request_id, (Peer::new(addr), hop_count));
+            },
+            Packet::Fragment { .. } => {
+                bail!("Fragments are reassembled by the Receiver");
+            },
+            Packet::Message { application_id, key, payload } => {
+                Self::route_message(network, applications, application_id, key, payload, None)?;
+            },
+            Packet::ReliableMessage { message_id, origin, application_id, key, payload } => {
+                let origin = origin.unwrap_or(addr);
+                Self::route_message(network, applications, application_id, key, payload, Some((message_id, Some(origin))))?;
+            },
+            Packet::Ack { message_id } => {
+                network.write().unwrap().get_acks_mut().complete(message_id, ());
             },
         }
         Ok(())
     }
 
-    fn run(network: Arc<RwLock<Network>>, running: Arc<RwLock<bool>>) {
+    /// Receive the packets until the framework stops.
+    /// The packets are handled by the workers if there are any, on this thread otherwise.
+    fn run(network: Arc<RwLock<Network<T>>>, applications: Applications, running: Arc<RwLock<bool>>, workers: Option<WorkerPool>) {
+        let receiver = network.read().unwrap().receiver();
         while *running.read().unwrap() {
-            let packet = network.read().unwrap().recv();
+            let packet = receiver.recv();
             if let std::result::Result::Ok((packet, addr)) = packet
             {
-                if let Err(e) = Self::handle_packet(network.clone(), packet, addr)
-                {
-                    // TODO: maybe log the error
+                match &workers {
+                    Some(workers) => {
+                        if !workers.dispatch(packet, addr)
+                        {
+                            // the queue of the worker is full, the packet is lost like in a full socket buffer
+                        }
+                    },
+                    None => {
+                        if let Err(_e) = Self::handle_packet(network.clone(), applications.clone(), packet, addr)
+                        {
+                            // TODO: maybe log the error
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    /// Remove the peers that missed too many probes and ping every peer in the routing table.
+    /// When a leaf is removed, the furthest leaf on the same side is asked for its leaf set to replace it.
+    /// When an entry of the routing table is removed, the other peers in the same row (then in the deeper rows)
+    /// are asked, one per probe, for their entry in the same position.
+    /// The closest leaf on each side is asked for its leaf set at every probe too,
+    /// so that the nodes that joined at the same time, each unknown to the root of the other, learn about each other.
+    fn probe(network: &Arc<RwLock<Network<T>>>, applications: &Applications) -> anyhow::Result<()> {
+        let (pings, leaf_set_repairs, entry_repairs, changes) = {
+            let mut network = network.write().unwrap();
+            let now = network.now();
+            let dead = network.get_liveness_mut().expire(now);
+            let mut changes = Vec::new();
+            let mut leaf_set_repairs = Vec::new();
+            for peer in dead {
+                let (removed, contact) = network.remove_peer(&peer);
+                if let Some(contact) = contact {
+                    if !leaf_set_repairs.contains(&contact) {
+                        leaf_set_repairs.push(contact);
+                    }
                 }
+                changes.extend(removed);
+                Self::remove_group_peer(&mut network, &peer)?;
+            }
+            Self::rejoin_groups(&mut network)?;
+            // the nearest leaves are asked for their leaf sets on every probe, not only after a failure,
+            // since two peers joining through the same root at the same time are not in each other's join responses
+            for leaf in network.get_routing_table().map(|routing_table| routing_table.leaf_set().nearest()).unwrap_or_default() {
+                if !leaf_set_repairs.contains(&leaf) {
+                    leaf_set_repairs.push(leaf);
+                }
+            }
+            let repairs = network.get_repairs_mut();
+            let entry_repairs: Vec<_> = repairs.pending().into_iter()
+                .filter_map(|(row, column)| repairs.next_contact(row, column).map(|contact| (row, column, contact)))
+                .collect();
+            let mut peers = network.get_routing_table().map(|routing_table| routing_table.peers()).unwrap_or_default();
+            for peer in network.get_groups().peers() {
+                if !peers.contains(&peer) {
+                    peers.push(peer);
+                }
+            }
+            for candidate in network.take_candidates() {
+                if !peers.contains(&candidate) {
+                    peers.push(candidate);
+                }
+            }
+            let pings: Vec<_> = peers.into_iter()
+                .map(|peer| (network.get_liveness_mut().probe(peer, now), peer))
+                .collect();
+            (pings, leaf_set_repairs, entry_repairs, changes)
+        };
+        Self::notify_leaf_set_change(network, applications, changes);
+
+        let network = network.read().unwrap();
+        for contact in leaf_set_repairs {
+            network.send(Packet::LeafSetRequest, contact.addr())?;
+        }
+        for (row, column, contact) in entry_repairs {
+            network.send(Packet::RoutingEntryRequest { row: row as u8, column }, contact.addr())?;
+        }
+        for (nonce, peer) in pings {
+            network.send(Packet::Ping { nonce }, peer.addr())?;
+        }
+        Ok(())
+    }
+
+    /// Run the probes and the anti-entropy if their interval elapsed.
+    fn run_due_maintenance(network: &Arc<RwLock<Network<T>>>, applications: &Applications) -> anyhow::Result<()> {
+        let (probe, anti_entropy, join_entry) = {
+            let mut network = network.write().unwrap();
+            let now = network.now();
+            let timeout = network.config().retransmission_timeout;
+            let join_entry = network.get_pending_join_mut().and_then(|pending_join| pending_join.retransmit(now, timeout));
+            (network.get_probe_timer_mut().fire(now), network.get_anti_entropy_timer_mut().fire(now), join_entry)
+        };
+        let probed = match probe {
+            true => Self::probe(network, applications),
+            false => Ok(()),
+        };
+        if anti_entropy {
+            Self::anti_entropy(network)?;
+        }
+        // a join packet was lost on the way
+        if let Some(entry_addr) = join_entry {
+            network.read().unwrap().send(Packet::JoinRequest, entry_addr)?;
+        }
+        probed
+    }
+
+    fn maintain(network: Arc<RwLock<Network<T>>>, applications: Applications, running: Arc<RwLock<bool>>) {
+        let config = network.read().unwrap().config().clone();
+        while *running.read().unwrap() {
+            // sleep for short periods to notice quickly when the framework is stopped
+            thread::sleep(config.socket_read_timeout);
+            if let Err(_e) = Self::run_due_maintenance(&network, &applications)
+            {
+                // TODO: maybe log the error
             }
         }
     }
 
+    /// Receive and handle the next packet on the calling thread,
+    /// to drive a framework that is not running, for example in a simulation.
+    pub fn poll(&self) -> anyhow::Result<()> {
+        let receiver = self.network.read().unwrap().receiver();
+        let (packet, addr) = receiver.recv()?;
+        Self::handle_packet(self.network.clone(), self.applications.clone(), packet, addr)
+    }
+
+    /// Run the maintenance that is due at the current time of the transport on the calling thread,
+    /// to drive a framework that is not running, for example in a simulation.
+    pub fn tick(&self) -> anyhow::Result<()> {
+        Self::run_due_maintenance(&self.network, &self.applications)
+    }
+
     pub fn start(&mut self) -> anyhow::Result<()> {
         {
             let mut running = self.running.write().unwrap();
@@ -123,16 +1083,88 @@ impl Framework {
             }
         }
         
+        let (worker_count, worker_queue_size) = {
+            let network = self.network.read().unwrap();
+            (network.config().workers, network.config().worker_queue_size)
+        };
+        let workers = match worker_count {
+            0 => None,
+            _ => {
+                let _network = self.network.clone();
+                let _applications = self.applications.clone();
+                let pool = WorkerPool::new(worker_count, worker_queue_size, move |packet, addr| {
+                    if let Err(_e) = Self::handle_packet(_network.clone(), _applications.clone(), packet, addr)
+                    {
+                        // TODO: maybe log the error
+                    }
+                });
+                let (workers, threads) = match pool {
+                    std::result::Result::Ok(pool) => pool,
+                    Err(e) => {
+                        *self.running.write().unwrap() = false;
+                        return Err(e);
+                    },
+                };
+                self.threads.extend(threads);
+                Some(workers)
+            },
+        };
+
+        // the workers stop once the network thread drops the pool
         let _running = self.running.clone();
         let _network = self.network.clone();
+        let _applications = self.applications.clone();
         self.threads.push(
         thread::Builder::new().name("network".to_string()).spawn(move || {
-            Self::run(_network, _running);
+            Self::run(_network, _applications, _running, workers);
+        })?);
+
+        let _running = self.running.clone();
+        let _network = self.network.clone();
+        let _applications = self.applications.clone();
+        self.threads.push(
+        thread::Builder::new().name("maintenance".to_string()).spawn(move || {
+            Self::maintain(_network, _applications, _running);
         })?);
 
         Ok(())
     }
 
+    /// Leave the network gracefully and stop the framework.
+    /// The stored keys are pushed to the leaves that replace this node,
+    /// then the peers in the routing table are notified so that they can replace this node immediately.
+    /// Fails without notifying anyone if the framework is not running.
+    pub fn leave(&mut self) -> anyhow::Result<()> {
+        // checked before notifying the peers, a leave that fails must not have removed this node from the network
+        if !*self.running.read().unwrap() {
+            bail!("Framework is not running");
+        }
+        self.hand_over()?;
+        self.stop()
+    }
+
+    /// Push the stored keys to the leaves that replace this node and notify the peers that it is leaving.
+    fn hand_over(&self) -> anyhow::Result<()> {
+        let mut network = self.network.write().unwrap();
+        let routing_table = match network.get_routing_table() {
+            Some(routing_table) => routing_table,
+            None => bail!("Not part of a network"),
+        };
+        let leaves = routing_table.leaves_to_vec();
+        // the leaves closest to each key are its replicas once this node is gone
+        for (key, entry) in network.get_storage().iter() {
+            for replica in routing_table.leaf_set().closest_leaves(key, network.config().replication_factor) {
+                network.send(Packet::Replicate { key: *key, entry: entry.clone() }, replica.addr())?;
+            }
+        }
+        for peer in routing_table.peers() {
+            network.send(Packet::Leaving { leaves: leaves.clone() }, peer.addr())?;
+        }
+        network.clear_routing_table();
+        Ok(())
+    }
+
+    /// Stop the framework without notifying the other peers, as if this node crashed.
     pub fn stop(&mut self) -> anyhow::Result<()> {
         {
             let mut running = self.running.write().unwrap();
@@ -144,11 +1176,162 @@ impl Framework {
         
         for thread in self.threads.drain(..) {
             let name = thread.thread().name().unwrap_or("Unknown").to_string();
-            if let Err(_) = thread.join() {
+            if thread.join().is_err() {
                 bail!("Failed to join thread: {}", name);
             }
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use crate::network::transport::simulated::{Link, SimulatedNetwork, SimulatedTransport};
+
+    use super::*;
+
+    fn config(bind_addr: SocketAddr) -> Config {
+        Config {
+            bind_addr,
+            entry_addr: bind_addr,
+            socket_read_timeout: Duration::from_millis(10),
+            socket_write_timeout: Duration::from_millis(10),
+            join_timeout: Duration::from_secs(2),
+            request_timeout: Duration::from_secs(1),
+            replication_factor: 3,
+            probe_interval: Duration::from_secs(1),
+            probe_timeout: Duration::from_millis(500),
+            max_missed_probes: 3,
+            dead_peer_timeout: Duration::from_secs(10),
+            anti_entropy_interval: Duration::from_secs(10),
+            tombstone_timeout: Duration::from_secs(60),
+            retransmission_timeout: Duration::from_millis(100),
+            max_retransmissions: 3,
+            duplicate_timeout: Duration::from_secs(10),
+            reassembly_timeout: Duration::from_secs(1),
+            max_reassembly_bytes: 0x100000,
+            workers: 0,
+            worker_queue_size: 0,
+        }
+    }
+
+    /// Deliver the datagrams to the nodes and run their maintenance every socket_read_timeout, for the given virtual time.
+    fn run(network: &SimulatedNetwork, nodes: &BTreeMap<SocketAddr, Framework<SimulatedTransport>>, duration: Duration) {
+        let deadline = network.now() + duration;
+        while network.now() < deadline {
+            let next_tick = network.now() + Duration::from_millis(10);
+            while network.next_arrival().is_some_and(|arrival| arrival <= next_tick) {
+                let addr = network.deliver_next();
+                if let Some((addr, node)) = addr.and_then(|addr| nodes.get(&addr).map(|node| (addr, node))) {
+                    while network.pending(&addr) > 0 {
+                        let _ = node.poll();
+                    }
+                }
+            }
+            network.advance(next_tick);
+            for node in nodes.values() {
+                let _ = node.tick();
+            }
+        }
+    }
+
+    #[test]
+    fn test_concurrent_joins() {
+        let network = SimulatedNetwork::new(42, Link::default());
+        let addrs: Vec<SocketAddr> = (0..3).map(|i| format!("10.0.0.{}:4848", i + 1).parse().unwrap()).collect();
+        let nodes: BTreeMap<_, _> = addrs.iter()
+            .map(|addr| (*addr, Framework::with_transport(network.bind(*addr).unwrap(), config(*addr))))
+            .collect();
+        nodes[&addrs[0]].bootstrap(addrs[0]).unwrap();
+
+        // the root answers both joins before the announce of either arrives, so its responses do not mention the other one
+        nodes[&addrs[1]].start_join(addrs[0]).unwrap();
+        nodes[&addrs[2]].start_join(addrs[0]).unwrap();
+        run(&network, &nodes, Duration::from_millis(100));
+        assert!(nodes.values().all(|node| node.local_peer().is_some()));
+
+        // they learn of each other from the leaf set of their nearest leaf
+        run(&network, &nodes, Duration::from_secs(2));
+        for node in nodes.values() {
+            let network = node.network.read().unwrap();
+            let leaves = network.get_routing_table().unwrap().leaves_to_vec();
+            for addr in addrs.iter().filter(|addr| **addr != network.local_peer().unwrap().addr()) {
+                assert!(leaves.contains(&Peer::new(*addr)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_suspected_entry_not_advertised() {
+        let network = SimulatedNetwork::new(42, Link::default());
+        let addrs: Vec<SocketAddr> = (0..2).map(|i| format!("10.0.0.{}:4848", i + 1).parse().unwrap()).collect();
+        let mut nodes: BTreeMap<_, _> = addrs.iter()
+            .map(|addr| (*addr, Framework::with_transport(network.bind(*addr).unwrap(), config(*addr))))
+            .collect();
+        nodes[&addrs[0]].bootstrap(addrs[0]).unwrap();
+        nodes[&addrs[1]].start_join(addrs[0]).unwrap();
+        run(&network, &nodes, Duration::from_millis(100));
+
+        let peer = Peer::new(addrs[1]);
+        let (row, column) = nodes[&addrs[0]].network.read().unwrap().get_routing_table().unwrap().slot(&peer.id()).unwrap();
+        let client_addr = "10.0.0.100:4848".parse().unwrap();
+        let client = network.bind(client_addr).unwrap();
+        let request = |nodes: &BTreeMap<SocketAddr, Framework<SimulatedTransport>>| {
+            client.send_to(&Packet::RoutingEntryRequest { row: row as u8, column }.serialize().unwrap(), addrs[0]).unwrap();
+            run(&network, nodes, Duration::from_millis(10));
+            let mut buf = [0; 0x10000];
+            while let std::result::Result::Ok((len, _)) = client.recv_from(&mut buf) {
+                if let Packet::RoutingEntryResponse { peer, .. } = Packet::deserialize(&buf[..len]).unwrap() {
+                    return peer;
+                }
+            }
+            panic!("No response to the routing entry request");
+        };
+        assert_eq!(request(&nodes), Some(peer));
+
+        // the peer crashes, its first missed probe is counted by the next probe, it is dead after max_missed_probes
+        nodes.remove(&addrs[1]);
+        run(&network, &nodes, Duration::from_millis(2500));
+        assert!(nodes[&addrs[0]].network.read().unwrap().get_liveness().is_suspected(&peer.id()));
+        assert_eq!(request(&nodes), None);
+    }
+
+    #[test]
+    fn test_tombstones_expire_on_transport_clock() {
+        let network = SimulatedNetwork::new(42, Link::default());
+        let addr: SocketAddr = "10.0.0.1:4848".parse().unwrap();
+        let nodes = BTreeMap::from([(addr, Framework::with_transport(network.bind(addr).unwrap(), config(addr)))]);
+        nodes[&addr].bootstrap(addr).unwrap();
+        let key = Id::from_key(0);
+        nodes[&addr].put(key, vec![0]).unwrap();
+        nodes[&addr].delete(key).unwrap();
+
+        // the tombstone is purged by the anti-entropy rounds once the virtual clock passes the tombstone timeout
+        run(&network, &nodes, Duration::from_secs(30));
+        assert!(nodes[&addr].network.read().unwrap().get_storage().entry(&key).is_some());
+        run(&network, &nodes, Duration::from_secs(40));
+        assert_eq!(nodes[&addr].network.read().unwrap().get_storage().entry(&key), None);
+    }
+
+    #[test]
+    fn test_lost_join_request() {
+        let network = SimulatedNetwork::new(42, Link::default());
+        let addrs: Vec<SocketAddr> = (0..2).map(|i| format!("10.0.0.{}:4848", i + 1).parse().unwrap()).collect();
+        let nodes: BTreeMap<_, _> = addrs.iter()
+            .map(|addr| (*addr, Framework::with_transport(network.bind(*addr).unwrap(), config(*addr))))
+            .collect();
+        nodes[&addrs[0]].bootstrap(addrs[0]).unwrap();
+
+        network.set_link(addrs[0], addrs[1], Link { loss: 1.0, ..Link::default() });
+        nodes[&addrs[1]].start_join(addrs[0]).unwrap();
+        run(&network, &nodes, Duration::from_millis(50));
+        assert_eq!(nodes[&addrs[1]].local_peer(), None);
+
+        // the join request is sent again after retransmission_timeout
+        network.set_link(addrs[0], addrs[1], Link::default());
+        run(&network, &nodes, Duration::from_millis(200));
+        assert_eq!(nodes[&addrs[1]].local_peer(), Some(Peer::new(addrs[1])));
+    }
+}
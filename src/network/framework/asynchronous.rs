@@ -0,0 +1,260 @@
+use std::{io, net::SocketAddr, sync::{Arc, RwLock}};
+
+use anyhow::bail;
+use tokio::{net::UdpSocket, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use crate::{id::Id, network::{application::{Application, ApplicationId}, config::Config, packet::Packet, peer::Peer, requests::PendingRequests, storage::{StorageOperation, StorageResult}, transport::Transport, Network}};
+
+use super::{Applications, Framework};
+
+/// The tokio socket as the transport of the AsyncFramework, it never blocks:
+/// sending fails if the socket buffer is full and receiving fails with WouldBlock if no datagram is queued,
+/// the receiving task waits for the socket to be readable instead.
+/// It is private because the receiving thread of a Framework would spin on it.
+#[derive(Debug)]
+struct AsyncSocket(UdpSocket);
+
+impl Transport for AsyncSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> anyhow::Result<()> {
+        self.0.try_send_to(buf, addr)?;
+        Ok(())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> anyhow::Result<(usize, SocketAddr)> {
+        Ok(self.0.try_recv_from(buf)?)
+    }
+}
+
+/// Runs a node of the overlay on the tokio runtime instead of dedicated threads.
+/// start spawns a task receiving the packets when the socket is readable and a task running the maintenance,
+/// both stop when the cancellation token is cancelled, either by shutdown or by the owner of the token.
+/// The packets and the maintenance are handled on the blocking threads of the runtime, under the locks of the Framework,
+/// so the application upcalls may block without stalling the runtime.
+/// The calls that wait for a response (join, lookup, put, get) wait asynchronously,
+/// but a message sent to a key this node is the closest to is delivered on the calling task, its upcall must not block.
+#[derive(Debug)]
+pub struct AsyncFramework {
+    framework: Framework<AsyncSocket>,
+    cancellation: CancellationToken,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl AsyncFramework {
+    /// Create a framework over a tokio UDP socket bound to the address in the config.
+    pub async fn new(config: Config) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(config.bind_addr).await?;
+        // the datagrams are sent without waiting, they would be dropped until the runtime knows that the socket is writable
+        socket.writable().await?;
+        Ok(Self {
+            framework: Framework::with_transport(AsyncSocket(socket), config),
+            cancellation: CancellationToken::new(),
+            tasks: Vec::new(),
+        })
+    }
+
+    /// Get the token that stops the framework when it is cancelled.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Register an application that will receive the upcalls for the messages with the given id.
+    pub fn register_application<A: Application + 'static>(&self, application_id: ApplicationId, application: A) -> anyhow::Result<()> {
+        self.framework.register_application(application_id, application)
+    }
+
+    /// Start a new network, this node will be the only peer.
+    /// public_addr is the address used by the other peers to reach this node.
+    pub fn bootstrap(&self, public_addr: SocketAddr) -> anyhow::Result<()> {
+        self.framework.bootstrap(public_addr)
+    }
+
+    /// Get the peer of this node as the other peers see it, None if it is not part of a network.
+    pub fn local_peer(&self) -> Option<Peer> {
+        self.framework.local_peer()
+    }
+
+    /// Spawn the receiving and the maintenance tasks on the current tokio runtime.
+    pub fn start(&mut self) -> anyhow::Result<()> {
+        if !self.tasks.is_empty() {
+            bail!("Framework is already running");
+        }
+        if self.cancellation.is_cancelled() {
+            bail!("Framework was shut down");
+        }
+
+        let network = self.framework.network.clone();
+        let applications = self.framework.applications.clone();
+        let cancellation = self.cancellation.clone();
+        self.tasks.push(tokio::spawn(async move {
+            let socket = network.read().unwrap().transport();
+            loop {
+                tokio::select! {
+                    _ = cancellation.cancelled() => break,
+                    readable = socket.0.readable() => {
+                        if readable.is_err() {
+                            break;
+                        }
+                        let (network, applications) = (network.clone(), applications.clone());
+                        if tokio::task::spawn_blocking(move || Self::receive(&network, &applications)).await.is_err() {
+                            break;
+                        }
+                    },
+                }
+            }
+        }));
+
+        let network = self.framework.network.clone();
+        let applications = self.framework.applications.clone();
+        let cancellation = self.cancellation.clone();
+        self.tasks.push(tokio::spawn(async move {
+            let period = network.read().unwrap().config().socket_read_timeout;
+            let mut interval = tokio::time::interval(period);
+            loop {
+                tokio::select! {
+                    _ = cancellation.cancelled() => break,
+                    _ = interval.tick() => {
+                        let (network, applications) = (network.clone(), applications.clone());
+                        match tokio::task::spawn_blocking(move || Framework::run_due_maintenance(&network, &applications)).await {
+                            Ok(Ok(())) => {},
+                            Ok(Err(_e)) => {
+                                // TODO: maybe log the error
+                            },
+                            Err(_e) => break,
+                        }
+                    },
+                }
+            }
+        }));
+
+        Ok(())
+    }
+
+    /// Handle the packets queued in the socket.
+    fn receive(network: &Arc<RwLock<Network<AsyncSocket>>>, applications: &Applications) {
+        let receiver = network.read().unwrap().receiver();
+        loop {
+            match receiver.recv() {
+                Ok((packet, addr)) => {
+                    if let Err(_e) = Framework::handle_packet(network.clone(), applications.clone(), packet, addr)
+                    {
+                        // TODO: maybe log the error
+                    }
+                },
+                Err(e) if e.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::WouldBlock) => break,
+                // a packet that cannot be deserialized is dropped
+                Err(_e) => continue,
+            }
+        }
+    }
+
+    /// Join the network through the peer at entry_addr.
+    /// The framework must be running, completes when the join completes or times out.
+    pub async fn join(&self, entry_addr: SocketAddr) -> anyhow::Result<()> {
+        if self.tasks.is_empty() || self.cancellation.is_cancelled() {
+            bail!("Framework is not running");
+        }
+        self.framework.start_join(entry_addr)?;
+        let config = self.framework.network.read().unwrap().config().clone();
+        let joined = tokio::time::timeout(config.join_timeout, async {
+            while self.framework.local_peer().is_none() {
+                tokio::time::sleep(config.socket_read_timeout).await;
+            }
+        }).await;
+        if joined.is_err() {
+            let mut network = self.framework.network.write().unwrap();
+            network.set_pending_join(None);
+            // the last response may have arrived after the last check
+            if network.get_routing_table().is_none() {
+                bail!("Join timed out");
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a message to the instance of the application running on the peer that is numerically closest to the key.
+    pub async fn send(&self, application_id: ApplicationId, key: Id, payload: Vec<u8>) -> anyhow::Result<()> {
+        self.framework.send(application_id, key, payload)
+    }
+
+    /// Find the peer numerically closest to the key.
+    /// Returns the peer and the number of hops the lookup took to reach it.
+    pub async fn lookup(&self, key: Id) -> anyhow::Result<(Peer, u8)> {
+        let network = &self.framework.network;
+        self.await_response(Network::get_lookups_mut, |request_id| Framework::route_lookup(network, request_id, None, key, 0)).await
+    }
+
+    /// Store the value in the key-value store of the peer numerically closest to the key.
+    pub async fn put(&self, key: Id, value: Vec<u8>) -> anyhow::Result<()> {
+        match self.storage_request(key, StorageOperation::Put { value }).await? {
+            StorageResult::Stored => Ok(()),
+            result => bail!("Unexpected storage result: {:?}", result),
+        }
+    }
+
+    /// Get the value from the key-value store of the peer numerically closest to the key.
+    /// Returns None if the key is not stored.
+    pub async fn get(&self, key: Id) -> anyhow::Result<Option<Vec<u8>>> {
+        match self.storage_request(key, StorageOperation::Get).await? {
+            StorageResult::Value(value) => Ok(value),
+            result => bail!("Unexpected storage result: {:?}", result),
+        }
+    }
+
+    async fn storage_request(&self, key: Id, operation: StorageOperation) -> anyhow::Result<StorageResult> {
+        let network = &self.framework.network;
+        self.await_response(Network::get_storage_requests_mut, |request_id| {
+            Framework::route_storage(network, Packet::Storage { request_id, origin: None, key, operation }, None)
+        }).await
+    }
+
+    /// Like Framework::await_response, but the response is awaited asynchronously.
+    async fn await_response<R>(&self, pending: impl Fn(&mut Network<AsyncSocket>) -> &mut PendingRequests<R>, send: impl FnOnce(u64) -> anyhow::Result<()>) -> anyhow::Result<R> {
+        let network = &self.framework.network;
+        let (request_id, receiver, timeout) = {
+            let mut network = network.write().unwrap();
+            let timeout = network.config().request_timeout;
+            let (request_id, receiver) = pending(&mut network).register_async();
+            (request_id, receiver, timeout)
+        };
+        if let Err(e) = send(request_id) {
+            pending(&mut network.write().unwrap()).cancel(request_id);
+            return Err(e);
+        }
+        match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(response)) => Ok(response),
+            _ => {
+                pending(&mut network.write().unwrap()).cancel(request_id);
+                bail!("The peer responsible for the key did not answer in time")
+            },
+        }
+    }
+
+    /// Leave the network gracefully, like Framework::leave, and shut the framework down.
+    /// Fails if the framework is not running.
+    pub async fn leave(&mut self) -> anyhow::Result<()> {
+        if self.tasks.is_empty() || self.cancellation.is_cancelled() {
+            bail!("Framework is not running");
+        }
+        self.framework.hand_over()?;
+        self.shutdown().await
+    }
+
+    /// Cancel the token and wait for the tasks to stop, without notifying the other peers.
+    pub async fn shutdown(&mut self) -> anyhow::Result<()> {
+        self.cancellation.cancel();
+        for task in self.tasks.drain(..) {
+            if let Err(e) = task.await {
+                bail!("Failed to join task: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AsyncFramework {
+    fn drop(&mut self) {
+        // the tasks would keep the socket open otherwise
+        self.cancellation.cancel();
+    }
+}
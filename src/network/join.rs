@@ -0,0 +1,94 @@
+use std::{collections::BTreeMap, net::SocketAddr, time::{Duration, Instant}};
+
+use super::{application::LeafSetChange, peer::Peer, routing::{routing_table::RoutingTable, routing_table_row::RoutingTableRow}};
+
+/// The state collected by a node while it is joining the network.
+/// The i-th hop of the join request sends the i-th row of the routing table,
+/// the last hop (the node numerically closest to the new one) sends the leaf set
+/// and the first hop (the entry point) sends the neighborhood set.
+/// The join packets are not acknowledged, the join request is sent again until the join completes.
+#[derive(Debug)]
+pub struct PendingJoin {
+    entry_addr: SocketAddr,
+    /// when the join request was last sent to the entry point
+    sent_at: Instant,
+    applicant: Option<Peer>,
+    rows: BTreeMap<u8, RoutingTableRow>,
+    responders: Vec<Peer>,
+    neighbors: Vec<Peer>,
+    root: Option<(u8, Peer, Vec<Peer>)>,
+}
+
+impl PendingJoin {
+    /// Start a join through the entry point at entry_addr, the join request is sent at now.
+    pub fn new(entry_addr: SocketAddr, now: Instant) -> Self {
+        Self {
+            entry_addr,
+            sent_at: now,
+            applicant: None,
+            rows: BTreeMap::new(),
+            responders: Vec::new(),
+            neighbors: Vec::new(),
+            root: None,
+        }
+    }
+
+    /// Get the entry point to send the join request to again if the join has not completed within timeout.
+    /// The responses already received are kept, every hop answers again and the missing ones fill the gaps.
+    pub fn retransmit(&mut self, now: Instant, timeout: Duration) -> Option<SocketAddr> {
+        if now.duration_since(self.sent_at) < timeout {
+            return None;
+        }
+        self.sent_at = now;
+        Some(self.entry_addr)
+    }
+
+    /// Add the response of the hop at the given position in the path of the join request.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_response(&mut self, responder: Peer, applicant: Peer, routing_table_row: RoutingTableRow, leaves: Vec<Peer>, neighbors: Vec<Peer>, hop_count: u8, is_last: bool) {
+        self.applicant = Some(applicant);
+        if hop_count == 0 {
+            self.neighbors = neighbors;
+            self.neighbors.push(responder);
+        }
+        self.rows.insert(hop_count, routing_table_row);
+        if !self.responders.contains(&responder) {
+            self.responders.push(responder);
+        }
+        if is_last {
+            self.root = Some((hop_count, responder, leaves));
+        }
+    }
+
+    /// Check if the last hop and every hop before it have responded.
+    pub fn is_complete(&self) -> bool {
+        match &self.root {
+            Some((last_hop, _, _)) => (0..=*last_hop).all(|hop| self.rows.contains_key(&hop)),
+            None => false,
+        }
+    }
+
+    /// Build the routing table of the new node merging every response.
+    /// Returns the new node as the other peers see it with its routing table, None if the join is not complete.
+    pub fn into_routing_table(self) -> Option<(Peer, RoutingTable, Vec<LeafSetChange>)> {
+        if !self.is_complete() {
+            return None;
+        }
+        let (_, root, mut leaves) = self.root?;
+        let applicant = self.applicant?;
+        let mut routing_table = RoutingTable::empty(applicant.id());
+        for row in self.rows.into_values() {
+            routing_table.add_row(row);
+        }
+        leaves.push(root);
+        let changes = routing_table.add_leaves(leaves.clone());
+        // the neighbors of the entry point are close to this node too, their round trip time is measured later
+        for neighbor in self.neighbors.iter() {
+            routing_table.add_neighbor(*neighbor);
+        }
+        for peer in self.neighbors.into_iter().chain(self.responders).chain(leaves) {
+            routing_table.insert(peer);
+        }
+        Some((applicant, routing_table, changes))
+    }
+}
@@ -0,0 +1,139 @@
+use std::{collections::{BTreeMap, HashMap}, time::{Duration, Instant}};
+
+use crate::id::Id;
+
+use super::peer::Peer;
+
+#[derive(Debug, Clone, Copy)]
+struct Probe {
+    peer: Peer,
+    sent_at: Instant,
+}
+
+/// Keeps track of the pings sent to the peers and of how many of them went unanswered.
+#[derive(Debug)]
+pub struct Liveness {
+    next_nonce: u64,
+    probes: BTreeMap<u64, Probe>,
+    missed_probes: HashMap<Id, u8>,
+    /// when the peers were declared dead
+    dead: HashMap<Id, Instant>,
+    probe_timeout: Duration,
+    max_missed_probes: u8,
+    dead_peer_timeout: Duration,
+}
+
+impl Liveness {
+    /// The nonces start from first_nonce, a random one so that the pongs to a previous run are not matched.
+    pub fn new(first_nonce: u64, probe_timeout: Duration, max_missed_probes: u8, dead_peer_timeout: Duration) -> Self {
+        Self {
+            next_nonce: first_nonce,
+            probes: BTreeMap::new(),
+            missed_probes: HashMap::new(),
+            dead: HashMap::new(),
+            probe_timeout,
+            max_missed_probes,
+            dead_peer_timeout,
+        }
+    }
+
+    /// Register a probe for the peer and return the nonce to send in the ping.
+    pub fn probe(&mut self, peer: Peer, now: Instant) -> u64 {
+        let nonce = self.next_nonce;
+        self.next_nonce = self.next_nonce.wrapping_add(1);
+        self.probes.insert(nonce, Probe { peer, sent_at: now });
+        nonce
+    }
+
+    /// Match a pong with its probe.
+    /// Returns the peer that answered and the round trip time, or None if the nonce is unknown.
+    pub fn pong(&mut self, nonce: u64, now: Instant) -> Option<(Peer, Duration)> {
+        let probe = self.probes.remove(&nonce)?;
+        self.missed_probes.remove(&probe.peer.id());
+        Some((probe.peer, now.duration_since(probe.sent_at)))
+    }
+
+    /// Check if the peer has missed some probes and is suspected to be dead.
+    pub fn is_suspected(&self, id: &Id) -> bool {
+        self.missed_probes.contains_key(id)
+    }
+
+    /// Expire the probes that timed out.
+    /// Returns the peers that missed too many probes in a row, they are considered dead and no longer tracked.
+    pub fn expire(&mut self, now: Instant) -> Vec<Peer> {
+        let expired: Vec<u64> = self.probes.iter()
+            .filter(|(_, probe)| now.duration_since(probe.sent_at) >= self.probe_timeout)
+            .map(|(nonce, _)| *nonce)
+            .collect();
+        let mut dead = Vec::new();
+        for nonce in expired {
+            let probe = self.probes.remove(&nonce).unwrap();
+            let missed = self.missed_probes.entry(probe.peer.id()).or_insert(0);
+            *missed = missed.saturating_add(1);
+            if *missed >= self.max_missed_probes && !dead.contains(&probe.peer) {
+                dead.push(probe.peer);
+            }
+        }
+        for peer in dead.iter() {
+            self.forget(&peer.id());
+            self.dead.insert(peer.id(), now);
+        }
+        self.dead.retain(|_, since| now.duration_since(*since) < self.dead_peer_timeout);
+        dead
+    }
+
+    /// Check if the peer was declared dead recently,
+    /// the other peers may still advertise it because they did not notice yet.
+    pub fn is_dead(&self, id: &Id) -> bool {
+        self.dead.contains_key(id)
+    }
+
+    /// Accept again a peer that was declared dead, because it contacted this node.
+    pub fn revive(&mut self, id: &Id) {
+        self.dead.remove(id);
+    }
+
+    /// Stop tracking the peer.
+    pub fn forget(&mut self, id: &Id) {
+        self.missed_probes.remove(id);
+        self.probes.retain(|_, probe| probe.peer.id() != *id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pong_resets_missed_probes() {
+        let mut liveness = Liveness::new(0, Duration::from_secs(1), 2, Duration::from_secs(10));
+        let peer = Peer::new("127.0.0.1:4848".parse().unwrap());
+        let now = Instant::now();
+
+        liveness.probe(peer, now);
+        assert!(liveness.expire(now + Duration::from_secs(2)).is_empty());
+        assert!(liveness.is_suspected(&peer.id()));
+
+        let nonce = liveness.probe(peer, now);
+        assert_eq!(liveness.pong(nonce, now + Duration::from_millis(10)), Some((peer, Duration::from_millis(10))));
+        assert!(!liveness.is_suspected(&peer.id()));
+        assert_eq!(liveness.pong(nonce, now), None);
+    }
+
+    #[test]
+    fn test_dead_after_missed_probes() {
+        let mut liveness = Liveness::new(0, Duration::from_secs(1), 2, Duration::from_secs(10));
+        let peer = Peer::new("127.0.0.1:4848".parse().unwrap());
+        let now = Instant::now();
+
+        liveness.probe(peer, now);
+        assert!(liveness.expire(now).is_empty());
+        assert!(liveness.expire(now + Duration::from_secs(1)).is_empty());
+        liveness.probe(peer, now + Duration::from_secs(1));
+        assert_eq!(liveness.expire(now + Duration::from_secs(2)), vec![peer]);
+        assert!(!liveness.is_suspected(&peer.id()));
+        assert!(liveness.is_dead(&peer.id()));
+        liveness.expire(now + Duration::from_secs(12));
+        assert!(!liveness.is_dead(&peer.id()));
+    }
+}
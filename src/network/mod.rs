@@ -4,6 +4,19 @@ pub mod peer_info;
 pub mod packet;
 pub mod config;
 pub mod framework;
+pub mod application;
+pub mod liveness;
+pub mod join;
+pub mod requests;
+pub mod storage;
+pub mod anti_entropy;
+pub mod scribe;
+pub mod reliable;
+pub mod fragmentation;
+pub mod transport;
+pub mod timer;
+pub mod simulator;
+pub mod workers;
 #[allow(clippy::module_inception)]
 mod network;
 pub use network::Network;
\ No newline at end of file
@@ -1,50 +1,155 @@
-use std::net::{SocketAddr, UdpSocket};
+use std::{net::{SocketAddr, UdpSocket}, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}, time::{Instant, SystemTime}};
 
 use anyhow::{bail, Ok};
 
 use crate::id::Id;
 
-use super::{config::Config, packet::Packet, peer::Peer, routing::routing_table::RoutingTable};
+use super::{anti_entropy::KeyRange, application::LeafSetChange, config::Config, fragmentation::{self, Reassembly}, transport::Transport, join::PendingJoin, liveness::Liveness, packet::Packet, reliable::Deliveries, requests::PendingRequests, scribe::Groups, timer::Timer, storage::{Storage, StorageResult}, peer::Peer, routing::{repair::Repairs, routing_table::RoutingTable}};
 
 const MTU: usize = 1500;
+const MAX_CANDIDATES: usize = 0x20;
 
+/// Receives the packets from the transport of a Network without holding the Network,
+/// so that the other threads are not blocked while waiting for a packet
 #[derive(Debug)]
-pub struct Network {
-    socket: UdpSocket,
+pub struct Receiver<T: Transport> {
+    transport: Arc<T>,
+    reassembly: Arc<Mutex<Reassembly>>,
+}
+
+impl<T: Transport> Receiver<T> {
+    /// Receive the next packet, reassembling the packets larger than the MTU from their fragments.
+    pub fn recv(&self) -> anyhow::Result<(Packet, SocketAddr)> {
+        let mut buf = [0; MTU];
+        loop {
+            let (len, addr) = self.transport.recv_from(&mut buf)?;
+            match Packet::deserialize(&buf[..len])? {
+                Packet::Fragment { message_id, index, count, data } => {
+                    let data = self.reassembly.lock().unwrap().add(addr, message_id, index, count, data, self.transport.now());
+                    if let Some(data) = data {
+                        return Ok((Packet::deserialize(&data)?, addr));
+                    }
+                },
+                packet => return Ok((packet, addr)),
+            }
+        }
+    }
+}
+
+/// The state of the node in the overlay, the packets are exchanged through the transport T.
+#[derive(Debug)]
+pub struct Network<T: Transport = UdpSocket> {
+    transport: Arc<T>,
+    reassembly: Arc<Mutex<Reassembly>>,
+    next_message_id: AtomicU64,
+    /// the address the other peers reach this node at, it differs from the bind address behind a NAT
+    /// or when bound to a wildcard address
+    public_addr: SocketAddr,
     routing_table: Option<RoutingTable>,
+    liveness: Liveness,
+    repairs: Repairs,
+    pending_join: Option<PendingJoin>,
+    candidates: Vec<Peer>,
+    storage: Storage,
+    storage_requests: PendingRequests<StorageResult>,
+    groups: Groups,
+    acks: PendingRequests<()>,
+    rpc_requests: PendingRequests<Option<Vec<u8>>>,
+    lookups: PendingRequests<(Peer, u8)>,
+    deliveries: Deliveries,
+    probe_timer: Timer,
+    anti_entropy_timer: Timer,
     config: Config,
 }
 
-impl Network {
+impl Network<UdpSocket> {
+    /// Create a network over a UDP socket bound to the address in the config.
+    pub fn new(config: Config) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(config.bind_addr)?;
+        socket.set_read_timeout(Some(config.socket_read_timeout))?;
+        socket.set_write_timeout(Some(config.socket_write_timeout))?;
+        Ok(Self::with_transport(socket, config))
+    }
+}
+
+impl<T: Transport> Network<T> {
     pub fn bootstrap(&mut self, public_addr: SocketAddr) -> anyhow::Result<()> {
         let peer = Peer::new(public_addr);
         let routing_table = RoutingTable::empty(peer.id());
+        self.public_addr = public_addr;
         self.set_routing_table(routing_table);
         Ok(())
     }
 
-    pub fn new(config: Config) -> anyhow::Result<Self> {
-        let socket = UdpSocket::bind(config.bind_addr)?;
-        socket.set_read_timeout(Some(config.socket_read_timeout))?;
-        socket.set_write_timeout(Some(config.socket_write_timeout))?;
-        Ok(Self {
-            socket,
+    /// Create a network over the given transport, reachable at the bind address in the config until it bootstraps or joins.
+    /// The socket timeouts in the config are left to the transport,
+    /// the first ids of the messages, requests and probes are drawn from it.
+    pub fn with_transport(transport: T, config: Config) -> Self {
+        let now = transport.now();
+        let transport = Arc::new(transport);
+        Self {
+            transport: transport.clone(),
+            reassembly: Arc::new(Mutex::new(Reassembly::new(config.reassembly_timeout, config.max_reassembly_bytes))),
+            // start from a random id so that fragments from a previous run are not matched
+            next_message_id: AtomicU64::new(transport.random()),
+            public_addr: config.bind_addr,
             routing_table: None,
+            liveness: Liveness::new(transport.random(), config.probe_timeout, config.max_missed_probes, config.dead_peer_timeout),
+            repairs: Repairs::new(),
+            pending_join: None,
+            candidates: Vec::new(),
+            storage: Storage::new(),
+            storage_requests: PendingRequests::new(transport.random()),
+            groups: Groups::new(),
+            acks: PendingRequests::new(transport.random()),
+            rpc_requests: PendingRequests::new(transport.random()),
+            lookups: PendingRequests::new(transport.random()),
+            deliveries: Deliveries::new(config.duplicate_timeout),
+            probe_timer: Timer::new(config.probe_interval, now),
+            anti_entropy_timer: Timer::new(config.anti_entropy_interval, now),
             config,
-        })
+        }
     }
 
+    /// Send the packet to the peer at addr, splitting it in fragments if it is larger than the MTU.
     pub fn send(&self, packet: Packet, addr: SocketAddr) -> anyhow::Result<()> {
         let buf = packet.serialize()?;
-        self.socket.send_to(&buf, addr)?;
+        if buf.len() <= MTU {
+            self.transport.send_to(&buf, addr)?;
+            return Ok(());
+        }
+        let message_id = self.next_message_id();
+        for fragment in fragmentation::fragment(message_id, &buf)? {
+            self.transport.send_to(&fragment.serialize()?, addr)?;
+        }
         Ok(())
     }
 
+    /// Get a new id for a message sent by this node.
+    pub fn next_message_id(&self) -> u64 {
+        self.next_message_id.fetch_add(1, Ordering::Relaxed)
+    }
+
     pub fn recv(&self) -> anyhow::Result<(Packet, SocketAddr)> {
-        let mut buf = [0; MTU];
-        let (len, addr) = self.socket.recv_from(&mut buf)?;
-        let packet = Packet::deserialize(&buf[..len])?;
-        Ok((packet, addr))
+        self.receiver().recv()
+    }
+
+    /// Get the current time of the transport.
+    pub fn now(&self) -> Instant {
+        self.transport.now()
+    }
+
+    /// Get the current wall-clock time of the transport.
+    pub fn system_time(&self) -> SystemTime {
+        self.transport.system_time()
+    }
+
+    pub fn transport(&self) -> Arc<T> {
+        self.transport.clone()
+    }
+
+    pub fn receiver(&self) -> Receiver<T> {
+        Receiver { transport: self.transport.clone(), reassembly: self.reassembly.clone() }
     }
 
     pub fn route(&self, id: &Id) -> anyhow::Result<Option<&Peer>> {
@@ -59,7 +164,175 @@ impl Network {
         self.routing_table.as_ref()
     }
 
+    pub fn get_routing_table_mut(&mut self) -> Option<&mut RoutingTable> {
+        self.routing_table.as_mut()
+    }
+
+    pub fn get_liveness(&self) -> &Liveness {
+        &self.liveness
+    }
+
+    pub fn get_liveness_mut(&mut self) -> &mut Liveness {
+        &mut self.liveness
+    }
+
+    pub fn get_repairs_mut(&mut self) -> &mut Repairs {
+        &mut self.repairs
+    }
+
+    pub fn get_pending_join_mut(&mut self) -> Option<&mut PendingJoin> {
+        self.pending_join.as_mut()
+    }
+
+    pub fn set_pending_join(&mut self, pending_join: Option<PendingJoin>) {
+        self.pending_join = pending_join;
+    }
+
+    pub fn take_pending_join(&mut self) -> Option<PendingJoin> {
+        self.pending_join.take()
+    }
+
+    pub fn get_storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    pub fn get_storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+
+    pub fn get_storage_requests_mut(&mut self) -> &mut PendingRequests<StorageResult> {
+        &mut self.storage_requests
+    }
+
+    pub fn get_groups(&self) -> &Groups {
+        &self.groups
+    }
+
+    pub fn get_groups_mut(&mut self) -> &mut Groups {
+        &mut self.groups
+    }
+
+    pub fn get_acks_mut(&mut self) -> &mut PendingRequests<()> {
+        &mut self.acks
+    }
+
+    pub fn get_rpc_requests_mut(&mut self) -> &mut PendingRequests<Option<Vec<u8>>> {
+        &mut self.rpc_requests
+    }
+
+    pub fn get_lookups_mut(&mut self) -> &mut PendingRequests<(Peer, u8)> {
+        &mut self.lookups
+    }
+
+    /// Get the peer of this node as the other peers see it, None if it is not part of a network.
+    pub fn local_peer(&self) -> Option<Peer> {
+        self.routing_table.as_ref().map(|routing_table| Peer::raw(routing_table.node_id(), self.public_addr))
+    }
+
+    pub fn get_deliveries_mut(&mut self) -> &mut Deliveries {
+        &mut self.deliveries
+    }
+
+    pub fn get_probe_timer_mut(&mut self) -> &mut Timer {
+        &mut self.probe_timer
+    }
+
+    pub fn get_anti_entropy_timer_mut(&mut self) -> &mut Timer {
+        &mut self.anti_entropy_timer
+    }
+
+    /// Set the address the other peers reach this node at, the one the entry point saw when joining.
+    pub fn set_public_addr(&mut self, public_addr: SocketAddr) {
+        self.public_addr = public_addr;
+    }
+
     pub fn set_routing_table(&mut self, routing_table: RoutingTable) {
         self.routing_table = Some(routing_table);
     }
+
+    pub fn clear_routing_table(&mut self) {
+        self.routing_table = None;
+    }
+
+    /// Remove a peer that failed or left from the routing table and stop tracking it,
+    /// the repair of its routing table entry is started.
+    /// Returns the changes in the leaf set and, if the peer was a leaf, the leaf to ask for its leaf set.
+    pub fn remove_peer(&mut self, peer: &Peer) -> (Vec<LeafSetChange>, Option<Peer>) {
+        self.liveness.forget(&peer.id());
+        let routing_table = match self.routing_table.as_mut() {
+            Some(routing_table) => routing_table,
+            None => return (Vec::new(), None),
+        };
+        let slot = routing_table.slot(&peer.id())
+            .filter(|&(row, column)| routing_table.entry(row, column) == Some(*peer));
+        let changes = routing_table.remove(&peer.id());
+        let contact = match changes.is_empty() {
+            true => None,
+            false => routing_table.leaf_set().furthest_towards(&peer.id()),
+        };
+        if let Some((row, column)) = slot {
+            self.repairs.start(row, column, routing_table.repair_contacts(row));
+        }
+        (changes, contact)
+    }
+
+    /// Offer the peers learned from the other nodes to the leaf set and to the routing table.
+    /// The peers recently declared dead are ignored.
+    /// The peers that lose their slot to the current entry because their round trip time is unknown
+    /// are kept as candidates to probe, the slot is contended again once the round trip time is measured.
+    /// Returns the changes in the leaf set.
+    pub fn add_peers(&mut self, peers: Vec<Peer>) -> Vec<LeafSetChange> {
+        let routing_table = match self.routing_table.as_mut() {
+            Some(routing_table) => routing_table,
+            None => return Vec::new(),
+        };
+        let mut changes = Vec::new();
+        for peer in peers {
+            if self.liveness.is_dead(&peer.id()) {
+                continue;
+            }
+            changes.extend(routing_table.add_leaves(vec![peer]));
+            if routing_table.insert(peer) || routing_table.rtt(&peer.id()).is_some() {
+                continue;
+            }
+            let is_entry = routing_table.slot(&peer.id())
+                .is_none_or(|(row, column)| routing_table.entry(row, column) == Some(peer));
+            if !is_entry && !self.candidates.contains(&peer) && self.candidates.len() < MAX_CANDIDATES {
+                self.candidates.push(peer);
+            }
+        }
+        changes
+    }
+
+    /// Get the leaves that should keep a replica of the key when this node is the closest to it,
+    /// the replication_factor - 1 leaves numerically closest to the key.
+    pub fn replicas(&self, key: &Id) -> Vec<Peer> {
+        match &self.routing_table {
+            Some(routing_table) => routing_table.leaf_set().closest_leaves(key, self.config.replication_factor.saturating_sub(1)),
+            None => Vec::new(),
+        }
+    }
+
+    /// Check if this node should keep a replica of the key,
+    /// that is if it is one of the replication_factor nodes numerically closest to it.
+    pub fn is_replica(&self, key: &Id) -> bool {
+        match &self.routing_table {
+            Some(routing_table) => routing_table.leaf_set().rank(key) < self.config.replication_factor,
+            None => false,
+        }
+    }
+
+    /// Get the range of keys this node is the closest to, None if it knows no other peer.
+    pub fn owned_range(&self) -> Option<KeyRange> {
+        self.routing_table.as_ref().and_then(|routing_table| routing_table.leaf_set().owned_range())
+    }
+
+    /// Take the candidates for the routing table that should be probed.
+    pub fn take_candidates(&mut self) -> Vec<Peer> {
+        std::mem::take(&mut self.candidates)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
 }
\ No newline at end of file
@@ -1,11 +1,14 @@
+use std::net::SocketAddr;
+
 use anyhow::Ok;
 use serde::{Deserialize, Serialize};
 
 use crate::id::Id;
 
-use super::{peer::Peer, routing::routing_table_row::RoutingTableRow};
+use super::{anti_entropy::KeyRange, application::ApplicationId, peer::Peer, storage::{Entry, StorageOperation, StorageResult}, routing::routing_table_row::RoutingTableRow};
 
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Packet {
     /// Send this to a peer to ask them to join the network
@@ -19,11 +22,52 @@ pub enum Packet {
     },
 
     /// Send this to a peer that required to join the network and you received a PeerIsJoining packet
+    /// is_last is true if you are the last hop, the peer numerically closest to the applicant
+    /// neighbors is your neighborhood set, the applicant uses the one of the first hop
+    /// applicant is the new peer with the address the entry point received the JoinRequest from,
+    /// the address the other peers reach it at
     JoinResponse {
-        applicant_id: Id,
+        applicant: Peer,
         routing_table_row: RoutingTableRow,
         leaves: Vec<Peer>,
+        neighbors: Vec<Peer>,
         hop_count: u8,
+        is_last: bool,
+    },
+
+    /// Send this to every peer in your routing table after joining the network,
+    /// so that they can add you and your leaves to their routing table
+    Announce {
+        leaves: Vec<Peer>,
+    },
+
+    /// Send this to every peer in your routing table before leaving the network,
+    /// so that they can replace you with your leaves without waiting for the failure detection
+    Leaving {
+        leaves: Vec<Peer>,
+    },
+
+    /// Send this to a peer to ask for its leaf set,
+    /// used to replace a leaf that failed with the leaves of the furthest leaf on the same side
+    LeafSetRequest,
+
+    /// Send this in response to a LeafSetRequest
+    LeafSetResponse {
+        leaves: Vec<Peer>,
+    },
+
+    /// Send this to a peer to ask for its entry at the given position of the routing table,
+    /// used to replace an entry that failed
+    RoutingEntryRequest {
+        row: u8,
+        column: u8,
+    },
+
+    /// Send this in response to a RoutingEntryRequest
+    RoutingEntryResponse {
+        row: u8,
+        column: u8,
+        peer: Option<Peer>,
     },
 
     Ping {
@@ -34,13 +78,170 @@ pub enum Packet {
         nonce: u64,
     },
 
+    /// Send this to execute an operation on the key-value store of the peer numerically closest to the key,
+    /// the origin is the address to send the result to, it is set by the first hop if missing
+    Storage {
+        request_id: u64,
+        origin: Option<SocketAddr>,
+        key: Id,
+        operation: StorageOperation,
+    },
+
+    /// Send this to the replicas of a key with its entry after storing or deleting it,
+    /// a replica keeps the newest of the entry and the one it stores
+    Replicate {
+        key: Id,
+        entry: Entry,
+    },
+
+    /// Send this to the replicas of the keys this node is the closest to,
+    /// with the root hash of the Merkle tree over the keys in the range
+    SyncRequest {
+        range: KeyRange,
+        hash: u64,
+    },
+
+    /// The answer of a replica whose keys in the range differ from the ones of the closest node,
+    /// with the digests of its keys if they are few, or None to ask for the hashes of the two halves of the range
+    SyncResponse {
+        range: KeyRange,
+        digests: Option<Vec<(Id, u64)>>,
+    },
+
+    /// Send this to a replica that stores keys the closest node is missing, or stores them in a newer version,
+    /// the replica hands them over to the closest node
+    SyncPull {
+        keys: Vec<Id>,
+    },
+
+    /// Routed to the node closest to the key to hand over an entry
+    /// stored by a node that is no longer the closest to it, or that the closest node is missing
+    Handoff {
+        key: Id,
+        entry: Entry,
+    },
+
+    /// Send this to a replica when the value is missing on the peer closest to the key,
+    /// if the replica is missing it too, it forwards the packet to the next address in remaining
+    /// the origin is set by the first replica if missing, like in Storage
+    ReplicaGet {
+        request_id: u64,
+        origin: Option<SocketAddr>,
+        key: Id,
+        remaining: Vec<SocketAddr>,
+    },
+
+    /// Send this to the origin of a Storage packet with the result of the operation
+    StorageResult {
+        request_id: u64,
+        result: StorageResult,
+    },
+
+    /// Routed towards the root of the topic, each hop adds the previous one as a child in the multicast tree
+    /// and stops the routing if it was already part of the tree
+    Subscribe {
+        topic: Id,
+    },
+
+    /// Send this to the parent in the multicast tree when this node has no more members or children
+    Unsubscribe {
+        topic: Id,
+    },
+
+    /// Routed to the root of the topic, that multicasts the payload down the tree
+    Publish {
+        topic: Id,
+        payload: Vec<u8>,
+    },
+
+    /// Send this to the children in the multicast tree,
+    /// message_id is chosen by the root so that a node forwards each message once even if the tree has a loop
+    Multicast {
+        topic: Id,
+        message_id: u64,
+        payload: Vec<u8>,
+    },
+
+    /// Delivered to the application on every peer, level is the length of the prefix shared by the ids
+    /// of the block the receiver is responsible for, see RoutingTable::broadcast_targets
+    Broadcast {
+        application_id: ApplicationId,
+        level: u8,
+        key: Id,
+        payload: Vec<u8>,
+    },
+
+    /// Routed towards the root of the topic and delivered to the first member of the group on the path,
+    /// once in the multicast tree it is forwarded depth first, visited holds the nodes of the tree already searched
+    Anycast {
+        topic: Id,
+        payload: Vec<u8>,
+        visited: Vec<Id>,
+    },
+
     /// Send this to send a generic message to a peer, 
     /// keep in mind that the closest peer to the key will receive the message,
     /// not necessarily the peer with the exact key
     Message {
+        application_id: ApplicationId,
         key: Id, 
         payload: Vec<u8>
     },
+
+    /// A Message that the closest peer to the key acknowledges to the origin,
+    /// the origin is set by the first hop if missing, like in Storage
+    ReliableMessage {
+        message_id: u64,
+        origin: Option<SocketAddr>,
+        application_id: ApplicationId,
+        key: Id,
+        payload: Vec<u8>,
+    },
+
+    /// Send this to the origin of a ReliableMessage after delivering it
+    Ack {
+        message_id: u64,
+    },
+
+    /// Routed to the closest peer to the key, that answers with a Response sent directly to the origin,
+    /// the origin is set by the first hop if missing, like in Storage
+    Request {
+        request_id: u64,
+        origin: Option<Peer>,
+        application_id: ApplicationId,
+        key: Id,
+        payload: Vec<u8>,
+    },
+
+    /// Send this to the origin of a Request with the response of the application,
+    /// None if the application is not registered or does not handle requests
+    Response {
+        request_id: u64,
+        payload: Option<Vec<u8>>,
+    },
+
+    /// Routed to the closest peer to the key, that answers with a LookupResult sent directly to the origin,
+    /// hop_count is incremented by every hop, the origin is set by the first hop if missing, like in Storage
+    Lookup {
+        request_id: u64,
+        origin: Option<SocketAddr>,
+        key: Id,
+        hop_count: u8,
+    },
+
+    /// Send this to the origin of a Lookup, the sender is the closest peer to the key
+    LookupResult {
+        request_id: u64,
+        hop_count: u8,
+    },
+
+    /// A piece of a serialized packet larger than the MTU, see fragmentation::fragment
+    Fragment {
+        message_id: u64,
+        index: u16,
+        count: u16,
+        data: Vec<u8>,
+    },
 }
 
 impl Packet {
@@ -1,4 +1,4 @@
-use std::net::SocketAddr;
+use std::{hash::{Hash, Hasher}, net::SocketAddr, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
@@ -6,13 +6,31 @@ use crate::id::Id;
 
 use super::peer_info::PeerInfo;
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+/// Two peers are equal if they have the same id and address, the info is not compared
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Peer {
     id: Id,
     addr: SocketAddr,
+    /// the info is local to each node, it is not sent to the other peers
+    #[serde(skip)]
     info: PeerInfo,
 }
 
+impl PartialEq for Peer {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.addr == other.addr
+    }
+}
+
+impl Eq for Peer {}
+
+impl Hash for Peer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+        self.addr.hash(state);
+    }
+}
+
 impl Peer {
     pub fn new(addr: SocketAddr) -> Self {
         let id = Id::from_key(addr);
@@ -34,4 +52,23 @@ impl Peer {
     pub fn addr(&self) -> SocketAddr {
         self.addr
     }
+
+    pub fn info(&self) -> PeerInfo {
+        self.info
+    }
+
+    /// Get the round trip time measured by this node, None if it was never measured.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.info.physical_distance_index.map(Duration::from_micros)
+    }
+
+    /// Update the round trip time with a new measurement,
+    /// the new measurement is averaged with the previous one to smooth out the spikes.
+    pub fn update_rtt(&mut self, rtt: Duration) {
+        let sample = rtt.as_micros().min(u64::MAX as u128) as u64;
+        self.info.physical_distance_index = Some(match self.info.physical_distance_index {
+            Some(previous) => previous - previous / 8 + sample / 8,
+            None => sample,
+        });
+    }
 }
\ No newline at end of file
@@ -1,15 +1,8 @@
 use serde::{Deserialize, Serialize};
 
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct PeerInfo {
+    /// smoothed round trip time in microseconds, measured with ping/pong
     pub physical_distance_index: Option<u64>, 
 }
-
-impl Default for PeerInfo {
-    fn default() -> Self {
-        Self {
-            physical_distance_index: None,
-        }
-    }
-}
\ No newline at end of file
@@ -0,0 +1,45 @@
+use std::{collections::HashMap, net::SocketAddr, time::{Duration, Instant}};
+
+/// Remembers the reliable messages delivered by this node, to suppress the retransmissions
+/// of the messages whose acknowledgement was lost.
+#[derive(Debug)]
+pub struct Deliveries {
+    /// when the message with the given id from the given origin was delivered
+    delivered: HashMap<(SocketAddr, u64), Instant>,
+    duplicate_timeout: Duration,
+}
+
+impl Deliveries {
+    pub fn new(duplicate_timeout: Duration) -> Self {
+        Self {
+            delivered: HashMap::new(),
+            duplicate_timeout,
+        }
+    }
+
+    /// Record the delivery of a message.
+    /// Returns false if the message was already delivered, the deliveries older than duplicate_timeout are forgotten.
+    pub fn deliver(&mut self, origin: SocketAddr, message_id: u64, now: Instant) -> bool {
+        self.delivered.retain(|_, delivered_at| now.duration_since(*delivered_at) < self.duplicate_timeout);
+        self.delivered.insert((origin, message_id), now).is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicates_are_suppressed() {
+        let mut deliveries = Deliveries::new(Duration::from_secs(10));
+        let origin = "127.0.0.1:4848".parse().unwrap();
+        let other = "127.0.0.1:4849".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(deliveries.deliver(origin, 1, now));
+        assert!(!deliveries.deliver(origin, 1, now + Duration::from_secs(1)));
+        assert!(deliveries.deliver(other, 1, now));
+        assert!(deliveries.deliver(origin, 2, now));
+        assert!(deliveries.deliver(origin, 1, now + Duration::from_secs(12)));
+    }
+}
@@ -0,0 +1,64 @@
+use std::{collections::HashMap, sync::mpsc};
+
+/// Where the response to a request is sent, depending on how the caller waits for it.
+#[derive(Debug)]
+enum Waiter<T> {
+    Blocking(mpsc::Sender<T>),
+    #[cfg(feature = "tokio")]
+    Async(tokio::sync::oneshot::Sender<T>),
+}
+
+/// Requests sent by this node that are waiting for a response.
+/// The caller waits on the receiver, the network thread completes the request when the response arrives.
+#[derive(Debug)]
+pub struct PendingRequests<T> {
+    next_id: u64,
+    pending: HashMap<u64, Waiter<T>>,
+}
+
+impl<T> PendingRequests<T> {
+    /// The ids start from first_id, a random one so that the responses to a previous run are not matched.
+    pub fn new(first_id: u64) -> Self {
+        Self {
+            next_id: first_id,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Register a new request.
+    /// Returns the id to send with the request and the receiver to wait on for the response.
+    pub fn register(&mut self) -> (u64, mpsc::Receiver<T>) {
+        let (sender, receiver) = mpsc::channel();
+        (self.insert(Waiter::Blocking(sender)), receiver)
+    }
+
+    /// Register a new request like register, the response can be awaited on the receiver.
+    #[cfg(feature = "tokio")]
+    pub fn register_async(&mut self) -> (u64, tokio::sync::oneshot::Receiver<T>) {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        (self.insert(Waiter::Async(sender)), receiver)
+    }
+
+    fn insert(&mut self, waiter: Waiter<T>) -> u64 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.pending.insert(id, waiter);
+        id
+    }
+
+    /// Complete the request with the response.
+    /// Returns false if the request is unknown, it was already completed or cancelled.
+    pub fn complete(&mut self, id: u64, response: T) -> bool {
+        match self.pending.remove(&id) {
+            Some(Waiter::Blocking(sender)) => sender.send(response).is_ok(),
+            #[cfg(feature = "tokio")]
+            Some(Waiter::Async(sender)) => sender.send(response).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Stop waiting for the response of the request.
+    pub fn cancel(&mut self, id: u64) {
+        self.pending.remove(&id);
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{id::Id, network::{anti_entropy::KeyRange, application::LeafSetChange, peer::Peer}};
+
+pub const HALF_LEAVES: usize = 0x4;
+
+/// The peers that are numerically closest to the node on the ring,
+/// HALF_LEAVES preceding it (left) and HALF_LEAVES following it (right).
+/// In small networks the same peer can be on both sides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LeafSet {
+    node_id: Id,
+    /// sorted from the closest to the furthest counterclockwise
+    left: [Option<Peer>; HALF_LEAVES],
+    /// sorted from the closest to the furthest clockwise
+    right: [Option<Peer>; HALF_LEAVES],
+}
+
+impl LeafSet {
+    /// Create a new empty leaf set for the given node.
+    pub fn empty(node_id: Id) -> Self {
+        Self {
+            node_id,
+            left: [None; HALF_LEAVES],
+            right: [None; HALF_LEAVES],
+        }
+    }
+
+    fn left_distance(&self, id: &Id) -> Id {
+        self.node_id.distance(id)
+    }
+
+    fn right_distance(&self, id: &Id) -> Id {
+        id.distance(&self.node_id)
+    }
+
+    /// Insert the peer in the sorted side if it is closer than the furthest leaf.
+    fn insert_side(side: &mut [Option<Peer>; HALF_LEAVES], peer: Peer, distance: impl Fn(&Id) -> Id) {
+        let mut peers: Vec<Peer> = side.iter().flatten().filter(|leaf| leaf.id() != peer.id()).copied().collect();
+        peers.push(peer);
+        peers.sort_by_key(|leaf| distance(&leaf.id()));
+        peers.truncate(HALF_LEAVES);
+        *side = [None; HALF_LEAVES];
+        for (slot, leaf) in side.iter_mut().zip(peers) {
+            *slot = Some(leaf);
+        }
+    }
+
+    fn remove_side(side: &mut [Option<Peer>; HALF_LEAVES], id: &Id) {
+        let peers: Vec<Peer> = side.iter().flatten().filter(|leaf| leaf.id() != *id).copied().collect();
+        *side = [None; HALF_LEAVES];
+        for (slot, leaf) in side.iter_mut().zip(peers) {
+            *slot = Some(leaf);
+        }
+    }
+
+    /// Get the changes between the leaves before and after an update.
+    fn changes(&self, before: &[Peer]) -> Vec<LeafSetChange> {
+        let after = self.to_vec();
+        let left = before.iter().filter(|leaf| !after.contains(leaf)).map(|leaf| LeafSetChange::Left(*leaf));
+        let joined = after.iter().filter(|leaf| !before.contains(leaf)).map(|leaf| LeafSetChange::Joined(*leaf));
+        left.chain(joined).collect()
+    }
+
+    /// Insert a peer in the leaf set, replacing the furthest leaf if the peer is closer.
+    /// Returns the changes in the leaf set.
+    pub fn insert(&mut self, peer: Peer) -> Vec<LeafSetChange> {
+        if peer.id() == self.node_id {
+            return Vec::new();
+        }
+        let before = self.to_vec();
+        let node_id = self.node_id;
+        Self::insert_side(&mut self.left, peer, |id| node_id.distance(id));
+        Self::insert_side(&mut self.right, peer, |id| id.distance(&node_id));
+        self.changes(&before)
+    }
+
+    /// Remove a peer from the leaf set.
+    /// Returns the changes in the leaf set.
+    pub fn remove(&mut self, id: &Id) -> Vec<LeafSetChange> {
+        let before = self.to_vec();
+        Self::remove_side(&mut self.left, id);
+        Self::remove_side(&mut self.right, id);
+        self.changes(&before)
+    }
+
+    pub fn contains(&self, id: &Id) -> bool {
+        self.iter().any(|leaf| leaf.id() == *id)
+    }
+
+    /// Iterate over the leaves, a peer on both sides is returned twice.
+    pub fn iter(&self) -> impl Iterator<Item = &Peer> {
+        self.left.iter().chain(self.right.iter()).flatten()
+    }
+
+    /// Iterate mutably over the leaves, the id of the leaves must not be changed.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Peer> {
+        self.left.iter_mut().chain(self.right.iter_mut()).flatten()
+    }
+
+    /// Get the leaves as a vector, without duplicates.
+    pub fn to_vec(&self) -> Vec<Peer> {
+        let mut leaves: Vec<Peer> = Vec::new();
+        for leaf in self.iter() {
+            if !leaves.contains(leaf) {
+                leaves.push(*leaf);
+            }
+        }
+        leaves
+    }
+
+    /// Check if the target falls between the furthest left leaf and the furthest right leaf.
+    pub fn covers(&self, target: &Id) -> bool {
+        let left = self.left.iter().flatten().last().map(|leaf| self.left_distance(&leaf.id()));
+        let right = self.right.iter().flatten().last().map(|leaf| self.right_distance(&leaf.id()));
+        left.is_some_and(|left| self.left_distance(target) <= left)
+            || right.is_some_and(|right| self.right_distance(target) <= right)
+    }
+
+    /// Find the leaf that is numerically closest to the target.
+    /// If the result is None, the current node is closer than every leaf.
+    pub fn closest(&self, target: &Id) -> Option<&Peer> {
+        let mut closest_peer = None;
+        let mut closest_distance = self.node_id.ring_distance(target);
+        for leaf in self.iter() {
+            let distance = leaf.id().ring_distance(target);
+            if distance < closest_distance {
+                closest_distance = distance;
+                closest_peer = Some(leaf);
+            }
+        }
+        closest_peer
+    }
+
+    /// Get the n leaves numerically closest to the target, from the closest to the furthest.
+    pub fn closest_leaves(&self, target: &Id, n: usize) -> Vec<Peer> {
+        let mut leaves = self.to_vec();
+        leaves.sort_by_key(|leaf| leaf.id().ring_distance(target));
+        leaves.truncate(n);
+        leaves
+    }
+
+    /// Get the range of keys the node is numerically closer to than its closest leaves,
+    /// None if the leaf set is empty.
+    pub fn owned_range(&self) -> Option<KeyRange> {
+        let left = self.left[0]?;
+        let right = self.right[0]?;
+        Some(KeyRange::new(left.id().midpoint(&self.node_id), self.node_id.midpoint(&right.id())))
+    }
+
+    /// Count the leaves that are numerically closer to the target than the node,
+    /// 0 means that the node is the closest one.
+    pub fn rank(&self, target: &Id) -> usize {
+        let distance = self.node_id.ring_distance(target);
+        self.to_vec().iter().filter(|leaf| leaf.id().ring_distance(target) < distance).count()
+    }
+
+    /// Get the closest leaf on each side, the same peer is returned once if it is on both sides.
+    pub fn nearest(&self) -> Vec<Peer> {
+        let mut nearest: Vec<Peer> = self.left[0].into_iter().collect();
+        if let Some(right) = self.right[0].filter(|right| !nearest.contains(right)) {
+            nearest.push(right);
+        }
+        nearest
+    }
+
+    /// Get the furthest leaf on the side of the ring where the given id is.
+    /// This is the peer to ask for its leaf set to repair the leaf set after the id has failed.
+    pub fn furthest_towards(&self, id: &Id) -> Option<Peer> {
+        if self.right_distance(id) < self.left_distance(id) {
+            self.right.iter().flatten().last().copied()
+        } else {
+            self.left.iter().flatten().last().copied()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn peer(id: &str) -> Peer {
+        Peer::raw(Id::from_str(id).unwrap(), "0.0.0.0:4848".parse().unwrap())
+    }
+
+    #[test]
+    fn test_insert_keeps_closest() {
+        let mut leaf_set = LeafSet::empty(Id::from_str("8000-0000-0000-0000").unwrap());
+        for i in 1..=6 {
+            leaf_set.insert(peer(&format!("8{}00-0000-0000-0000", i)));
+            leaf_set.insert(peer(&format!("7{}00-0000-0000-0000", 9 - i)));
+        }
+        let right: Vec<_> = leaf_set.right.iter().flatten().copied().collect();
+        let left: Vec<_> = leaf_set.left.iter().flatten().copied().collect();
+        assert_eq!(right, (1..=4).map(|i| peer(&format!("8{}00-0000-0000-0000", i))).collect::<Vec<_>>());
+        assert_eq!(left, (1..=4).map(|i| peer(&format!("7{}00-0000-0000-0000", 9 - i))).collect::<Vec<_>>());
+
+        let changes = leaf_set.insert(peer("8080-0000-0000-0000"));
+        assert_eq!(changes, vec![
+            LeafSetChange::Left(peer("8400-0000-0000-0000")),
+            LeafSetChange::Joined(peer("8080-0000-0000-0000")),
+        ]);
+        assert!(leaf_set.insert(peer("8f00-0000-0000-0000")).is_empty());
+    }
+
+    #[test]
+    fn test_small_network_wraps_around() {
+        let mut leaf_set = LeafSet::empty(Id::from_str("0100-0000-0000-0000").unwrap());
+        let changes = leaf_set.insert(peer("f000-0000-0000-0000"));
+        assert_eq!(changes, vec![LeafSetChange::Joined(peer("f000-0000-0000-0000"))]);
+        assert_eq!(leaf_set.to_vec(), vec![peer("f000-0000-0000-0000")]);
+        assert_eq!(leaf_set.closest(&Id::from_str("f100-0000-0000-0000").unwrap()), Some(&peer("f000-0000-0000-0000")));
+        assert_eq!(leaf_set.closest(&Id::from_str("0200-0000-0000-0000").unwrap()), None);
+    }
+
+    #[test]
+    fn test_remove_and_repair_contact() {
+        let mut leaf_set = LeafSet::empty(Id::from_str("8000-0000-0000-0000").unwrap());
+        for i in 1..=4 {
+            leaf_set.insert(peer(&format!("8{}00-0000-0000-0000", i)));
+            leaf_set.insert(peer(&format!("7{:x}00-0000-0000-0000", 16 - i)));
+        }
+
+        let dead = Id::from_str("8100-0000-0000-0000").unwrap();
+        assert_eq!(leaf_set.remove(&dead), vec![LeafSetChange::Left(peer("8100-0000-0000-0000"))]);
+        assert!(!leaf_set.contains(&dead));
+        assert_eq!(leaf_set.furthest_towards(&dead), Some(peer("8400-0000-0000-0000")));
+        assert_eq!(leaf_set.furthest_towards(&Id::from_str("7f00-0000-0000-0000").unwrap()), Some(peer("7c00-0000-0000-0000")));
+        assert!(leaf_set.covers(&Id::from_str("8180-0000-0000-0000").unwrap()));
+        assert!(!leaf_set.covers(&Id::from_str("9000-0000-0000-0000").unwrap()));
+    }
+
+    #[test]
+    fn test_rank() {
+        let mut leaf_set = LeafSet::empty(Id::from_str("8000-0000-0000-0000").unwrap());
+        for i in 1..=4 {
+            leaf_set.insert(peer(&format!("8{}00-0000-0000-0000", i)));
+        }
+        assert_eq!(leaf_set.rank(&Id::from_str("7f00-0000-0000-0000").unwrap()), 0);
+        assert_eq!(leaf_set.rank(&Id::from_str("8100-0000-0000-0000").unwrap()), 1);
+        assert_eq!(leaf_set.rank(&Id::from_str("8180-0000-0000-0000").unwrap()), 2);
+        leaf_set.insert(peer("7000-0000-0000-0000"));
+        assert_eq!(leaf_set.owned_range(), Some(KeyRange::new(Id::from_str("7800-0000-0000-0000").unwrap(), Id::from_str("8080-0000-0000-0000").unwrap())));
+    }
+}
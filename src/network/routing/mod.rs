@@ -1,2 +1,5 @@
 pub mod routing_table;
-pub mod routing_table_row;
\ No newline at end of file
+pub mod routing_table_row;
+pub mod leaf_set;
+pub mod repair;
+pub mod neighborhood_set;
\ No newline at end of file
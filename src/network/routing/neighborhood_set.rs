@@ -0,0 +1,80 @@
+use crate::{id::Id, network::peer::Peer};
+
+pub const NEIGHBORHOOD_SIZE: usize = 0x8;
+
+/// The peers that are physically closest to the node, by measured round trip time.
+/// Peers whose round trip time was not measured yet come last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NeighborhoodSet {
+    node_id: Id,
+    /// sorted from the closest to the furthest
+    neighbors: [Option<Peer>; NEIGHBORHOOD_SIZE],
+}
+
+impl NeighborhoodSet {
+    /// Create a new empty neighborhood set for the given node.
+    pub fn empty(node_id: Id) -> Self {
+        Self {
+            node_id,
+            neighbors: [None; NEIGHBORHOOD_SIZE],
+        }
+    }
+
+    fn set(&mut self, mut neighbors: Vec<Peer>) {
+        // unknown round trip times are sorted after the measured ones
+        neighbors.sort_by_key(|neighbor| (neighbor.rtt().is_none(), neighbor.rtt()));
+        neighbors.truncate(NEIGHBORHOOD_SIZE);
+        self.neighbors = [None; NEIGHBORHOOD_SIZE];
+        for (slot, neighbor) in self.neighbors.iter_mut().zip(neighbors) {
+            *slot = Some(neighbor);
+        }
+    }
+
+    /// Insert a peer, replacing the furthest neighbor if the peer is closer.
+    /// If the peer is already a neighbor, it is replaced unless its round trip time is unknown.
+    /// Returns true if the peer is in the neighborhood set after the insertion.
+    pub fn insert(&mut self, peer: Peer) -> bool {
+        if peer.id() == self.node_id {
+            return false;
+        }
+        let mut neighbors: Vec<Peer> = self.iter().copied().collect();
+        match neighbors.iter_mut().find(|neighbor| neighbor.id() == peer.id()) {
+            Some(neighbor) if peer.rtt().is_some() => *neighbor = peer,
+            Some(_) => {},
+            None => neighbors.push(peer),
+        }
+        self.set(neighbors);
+        self.contains(&peer.id())
+    }
+
+    /// Remove a peer from the neighborhood set.
+    pub fn remove(&mut self, id: &Id) {
+        let neighbors = self.iter().filter(|neighbor| neighbor.id() != *id).copied().collect();
+        self.set(neighbors);
+    }
+
+    pub fn contains(&self, id: &Id) -> bool {
+        self.iter().any(|neighbor| neighbor.id() == *id)
+    }
+
+    /// Iterate over the neighbors, from the closest to the furthest.
+    pub fn iter(&self) -> impl Iterator<Item = &Peer> {
+        self.neighbors.iter().flatten()
+    }
+
+    /// Iterate mutably over the neighbors, the order must be restored after changing their round trip time.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Peer> {
+        self.neighbors.iter_mut().flatten()
+    }
+
+    /// Sort the neighbors again after their round trip times changed.
+    pub fn sort(&mut self) {
+        let neighbors = self.iter().copied().collect();
+        self.set(neighbors);
+    }
+
+    /// Get the neighbors as a vector, from the closest to the furthest.
+    pub fn to_vec(&self) -> Vec<Peer> {
+        self.iter().copied().collect()
+    }
+}
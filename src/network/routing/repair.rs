@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+
+use crate::network::peer::Peer;
+
+/// Keeps track of the routing table entries that are being repaired.
+/// For each entry, the peers that can still be asked for a replacement are stored in the order they should be asked.
+#[derive(Debug, Default)]
+pub struct Repairs {
+    pending: BTreeMap<(usize, u8), Vec<Peer>>,
+}
+
+impl Repairs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start repairing the entry, replacing any previous repair of the same entry.
+    pub fn start(&mut self, row: usize, column: u8, mut contacts: Vec<Peer>) {
+        contacts.reverse();
+        self.pending.insert((row, column), contacts);
+    }
+
+    /// Get the next peer to ask for a replacement of the entry.
+    /// If there are no more peers to ask, the repair is abandoned.
+    pub fn next_contact(&mut self, row: usize, column: u8) -> Option<Peer> {
+        let contacts = self.pending.get_mut(&(row, column))?;
+        let contact = contacts.pop();
+        if contact.is_none() {
+            self.pending.remove(&(row, column));
+        }
+        contact
+    }
+
+    /// Stop repairing the entry.
+    pub fn complete(&mut self, row: usize, column: u8) {
+        self.pending.remove(&(row, column));
+    }
+
+    pub fn is_pending(&self, row: usize, column: u8) -> bool {
+        self.pending.contains_key(&(row, column))
+    }
+
+    /// Get the entries that are being repaired.
+    pub fn pending(&self) -> Vec<(usize, u8)> {
+        self.pending.keys().copied().collect()
+    }
+}
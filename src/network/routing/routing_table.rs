@@ -1,14 +1,16 @@
-use crate::{id::Id, network::peer::Peer};
+use std::time::Duration;
 
-use super::routing_table_row::RoutingTableRow;
+use crate::{id::Id, network::{application::LeafSetChange, peer::Peer}};
+
+use super::{leaf_set::LeafSet, neighborhood_set::NeighborhoodSet, routing_table_row::RoutingTableRow};
 
-const HALF_LEAVES: usize = 0x4;
 const ROWS: usize = 0x8;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct RoutingTable {
     node_id: Id,
-    leaves: [Option<Peer>; HALF_LEAVES*2],
+    leaf_set: LeafSet,
+    neighborhood_set: NeighborhoodSet,
     table_rows: [RoutingTableRow; ROWS],
 }
 
@@ -18,56 +20,206 @@ impl RoutingTable {
     pub fn empty(node_id: Id) -> Self {
         Self {
             node_id,
-            leaves: [None; HALF_LEAVES*2],
+            leaf_set: LeafSet::empty(node_id),
+            neighborhood_set: NeighborhoodSet::empty(node_id),
             table_rows: [RoutingTableRow::empty(); ROWS],
         }
     }
 
-    /// Set a row of the routing table.
-    /// If the row is out of bounds, it is ignored.
-    pub fn set_row(&mut self, row: RoutingTableRow, index: usize) {
-        if index < ROWS {
-            self.table_rows[index] = row;
-            self.table_rows[index][self.node_id.get_digit(index)] = None;
+    pub fn node_id(&self) -> Id {
+        self.node_id
+    }
+
+    /// Add the entries of a row received from another peer.
+    /// The row fits as it is only if the peer shares enough digits with the node,
+    /// which is not the case for a hop of the join that was reached through the leaf set,
+    /// so every entry is inserted in the slot where it belongs.
+    pub fn add_row(&mut self, row: RoutingTableRow) {
+        for peer in row.iter().flatten() {
+            self.insert(*peer);
         }
     }
 
-    /// Add leaves to the routing table.
-    pub fn add_leaves(&mut self, leaves: Vec<Peer>) {
-        for leaf in leaves {
-            if leaf.id() < self.node_id {
-                for i in 0..HALF_LEAVES {
-                    if self.leaves[i].is_none() {
-                        self.leaves[i] = Some(leaf);
-                        break;
-                    }
-                }
+    /// Add leaves to the leaf set, the closest ones replace the furthest ones.
+    /// Returns the changes in the leaf set.
+    pub fn add_leaves(&mut self, leaves: Vec<Peer>) -> Vec<LeafSetChange> {
+        leaves.into_iter().flat_map(|leaf| {
+            let leaf = self.known(leaf);
+            self.leaf_set.insert(leaf)
+        }).collect()
+    }
+
+    /// Get the leaves of the routing table as a vector.
+    pub fn leaves_to_vec(&self) -> Vec<Peer> {
+        self.leaf_set.to_vec()
+    }
+
+    pub fn leaf_set(&self) -> &LeafSet {
+        &self.leaf_set
+    }
+
+    /// Add a peer to the neighborhood set, it is kept only if it is among the physically closest.
+    pub fn add_neighbor(&mut self, peer: Peer) {
+        let peer = self.known(peer);
+        self.neighborhood_set.insert(peer);
+    }
+
+    fn peers_mut(&mut self) -> impl Iterator<Item = &mut Peer> {
+        let rows = self.table_rows.iter_mut().flat_map(|row| row.iter_mut().flatten());
+        self.leaf_set.iter_mut().chain(self.neighborhood_set.iter_mut()).chain(rows)
+    }
+
+    /// Get the round trip time to the peer measured by this node, if it is known.
+    pub fn rtt(&self, id: &Id) -> Option<Duration> {
+        let rows = self.table_rows.iter().flat_map(|row| row.iter().flatten());
+        self.leaf_set.iter().chain(self.neighborhood_set.iter()).chain(rows)
+            .filter(|peer| peer.id() == *id)
+            .find_map(|peer| peer.rtt())
+    }
+
+    /// Get the peer with the info known by this node, the peers received from the network carry no info.
+    fn known(&self, peer: Peer) -> Peer {
+        let rows = self.table_rows.iter().flat_map(|row| row.iter().flatten());
+        self.leaf_set.iter().chain(self.neighborhood_set.iter()).chain(rows)
+            .find(|known| known.id() == peer.id() && known.rtt().is_some())
+            .copied()
+            .unwrap_or(peer)
+    }
+
+    /// Record a new round trip time measurement for the peer and update every copy of it.
+    /// The peer is then offered to the neighborhood set and to its slot,
+    /// where it replaces the current entry if it is physically closer.
+    pub fn update_rtt(&mut self, peer: Peer, rtt: Duration) {
+        let mut peer = self.known(peer);
+        peer.update_rtt(rtt);
+        for known in self.peers_mut() {
+            if known.id() == peer.id() {
+                *known = peer;
             }
-            else if leaf.id() > self.node_id {
-                for i in HALF_LEAVES..HALF_LEAVES*2 {
-                    if self.leaves[i].is_none() {
-                        self.leaves[i] = Some(leaf);
-                        break;
-                    }
+        }
+        self.neighborhood_set.sort();
+        self.neighborhood_set.insert(peer);
+        self.insert(peer);
+    }
+
+    pub fn neighborhood_set(&self) -> &NeighborhoodSet {
+        &self.neighborhood_set
+    }
+
+    /// Get every peer known by the routing table, leaves and neighbors included, without duplicates.
+    pub fn peers(&self) -> Vec<Peer> {
+        let mut peers: Vec<Peer> = Vec::new();
+        let leaves = self.leaf_set.iter();
+        let neighbors = self.neighborhood_set.iter();
+        let rows = self.table_rows.iter().flat_map(|row| row.iter().flatten());
+        for peer in leaves.chain(neighbors).chain(rows) {
+            if !peers.iter().any(|p| p.id() == peer.id()) {
+                peers.push(*peer);
+            }
+        }
+        peers
+    }
+
+    /// Remove a peer from the leaves, the neighbors and the rows of the routing table.
+    /// Returns the changes in the leaf set.
+    pub fn remove(&mut self, id: &Id) -> Vec<LeafSetChange> {
+        let changes = self.leaf_set.remove(id);
+        self.neighborhood_set.remove(id);
+        for row in self.table_rows.iter_mut() {
+            for entry in row.iter_mut() {
+                if entry.is_some_and(|peer| peer.id() == *id) {
+                    *entry = None;
                 }
             }
         }
+        changes
     }
 
-    /// Get the leaves of the routing table as a vector.
-    pub fn leaves_to_vec(&self) -> Vec<Peer> {
-        self.leaves.iter().flatten().cloned().collect()
+    /// Get the position (row, column) that the peer with the given id would take in the routing table.
+    /// Returns None for the current node and for the ids that share too many digits with it.
+    pub fn slot(&self, id: &Id) -> Option<(usize, u8)> {
+        let row = self.node_id.shared_prefix_len(id);
+        if row < ROWS {
+            Some((row, id.get_digit(row)))
+        } else {
+            None
+        }
+    }
+
+    /// Insert the peer in its slot of the routing table if the slot is empty,
+    /// or if the measured round trip time of the peer is lower than the one of the current entry.
+    /// Returns true if the peer was inserted.
+    pub fn insert(&mut self, peer: Peer) -> bool {
+        let (row, column) = match self.slot(&peer.id()) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        let peer = self.known(peer);
+        let closer = match self.table_rows[row][column] {
+            None => true,
+            Some(current) if current.id() == peer.id() => false,
+            Some(current) => match (peer.rtt(), current.rtt()) {
+                (Some(rtt), Some(current_rtt)) => rtt < current_rtt,
+                (Some(_), None) => true,
+                (None, _) => false,
+            },
+        };
+        if closer {
+            self.table_rows[row][column] = Some(peer);
+        }
+        closer
+    }
+
+    /// Get the entry of the routing table at the given position.
+    pub fn entry(&self, row: usize, column: u8) -> Option<Peer> {
+        self.table_rows.get(row).and_then(|table_row| table_row.iter().nth(column as usize).copied().flatten())
+    }
+
+    /// Get the peers to ask for a replacement of an entry in the given row:
+    /// the other peers in the same row first, then the peers in the deeper rows.
+    pub fn repair_contacts(&self, row: usize) -> Vec<Peer> {
+        self.table_rows.iter()
+            .skip(row)
+            .flat_map(|table_row| table_row.iter().flatten().copied())
+            .collect()
     }
 
     /// Get the row of the routing table at the given index.
     /// If the index is out of bounds, an empty row is returned.
     pub fn row(&self, index: usize) -> RoutingTableRow {
-        self.table_rows.get(index).unwrap_or(&RoutingTableRow::empty()).clone()
+        *self.table_rows.get(index).unwrap_or(&RoutingTableRow::empty())
+    }
+
+    /// Get the peers to forward a broadcast to, one for each block of the id space this node is responsible for.
+    /// The block (row, column) holds the ids that share exactly row digits with the node and have column as the next digit,
+    /// a node is responsible for the blocks from its level, the chosen peer is responsible for the blocks inside its own block,
+    /// so that every peer receives the broadcast once.
+    /// The routing table entry of a block is preferred, any other known peer in the block is used if the entry is missing.
+    /// Returns the peers with their level.
+    pub fn broadcast_targets(&self, level: usize) -> Vec<(Peer, usize)> {
+        let mut targets: Vec<(Peer, usize)> = Vec::new();
+        for peer in self.peers() {
+            let row = self.node_id.shared_prefix_len(&peer.id());
+            if row < level {
+                continue;
+            }
+            let peer = self.entry(row, peer.id().get_digit(row)).unwrap_or(peer);
+            // the peers in the same block share more digits than the ones shared with the node
+            if !targets.iter().any(|(target, _)| target.id().shared_prefix_len(&peer.id()) > row) {
+                targets.push((peer, row + 1));
+            }
+        }
+        targets
     }
 
     /// Find the next hop to reach the closest peer to the target.
     /// If the result is None, the closest peer is the current node or the network has failed.
     pub fn route(&self, target: &Id) -> Option<&Peer> {
+        // the target is in the range of the leaf set, jump directly to the closest leaf
+        if self.leaf_set.covers(target) {
+            return self.leaf_set.closest(target);
+        }
+
         // long jump
         for (i,row) in self.table_rows.iter().enumerate() {
             if target.get_digit(i) != self.node_id.get_digit(i) {
@@ -78,14 +230,16 @@ impl RoutingTable {
             }
         }
 
-        // short jump
+        // short jump, to any known peer that shares at least the same prefix with the target and is closer
+        let prefix_len = self.node_id.shared_prefix_len(target);
         let mut closest_peer = None;
-        let mut closest_distance = self.node_id.distance(target);
-        for leaf in self.leaves.iter().flatten() {
-            let distance = leaf.distance(target);
-            if distance < closest_distance {
+        let mut closest_distance = self.node_id.ring_distance(target);
+        let rows = self.table_rows.iter().flat_map(|row| row.iter().flatten());
+        for peer in self.leaf_set.iter().chain(rows) {
+            let distance = peer.id().ring_distance(target);
+            if peer.id().shared_prefix_len(target) >= prefix_len && distance < closest_distance {
                 closest_distance = distance;
-                closest_peer = Some(leaf);
+                closest_peer = Some(peer);
             }
         }
 
@@ -105,7 +259,8 @@ mod tests {
         let node_id = Id::from_key("node");
         let table = RoutingTable {
             node_id,
-            leaves: [None; HALF_LEAVES*2],
+            leaf_set: LeafSet::empty(node_id),
+            neighborhood_set: NeighborhoodSet::empty(node_id),
             table_rows: [RoutingTableRow::empty(); ROWS],
         };
 
@@ -117,13 +272,14 @@ mod tests {
     fn test_short_jump() {
         let node_id = Id::from_str("2000-0000-0000-0000").unwrap();
         let addr = "0.0.0.0:4848".parse().unwrap();
-        let mut leaves = [None; HALF_LEAVES*2];
-        leaves[HALF_LEAVES] = Some(Peer::raw(Id::from_str("1000-0000-0000-0000").unwrap(),addr));
+        let mut leaf_set = LeafSet::empty(node_id);
+        leaf_set.insert(Peer::raw(Id::from_str("1000-0000-0000-0000").unwrap(),addr));
         let mut table_rows = [RoutingTableRow::empty(); ROWS];
         table_rows[0][0] = Some(Peer::raw(Id::from_str("0000-0000-0000-0000").unwrap(),addr));
         let table = RoutingTable {
             node_id,
-            leaves,
+            leaf_set,
+            neighborhood_set: NeighborhoodSet::empty(node_id),
             table_rows,
         };
 
@@ -143,7 +299,8 @@ mod tests {
         table_rows[2][2] = Some(Peer::raw(Id::from_str("2020-0000-0000-0000").unwrap(),addr));
         let table = RoutingTable {
             node_id,
-            leaves: [None; HALF_LEAVES*2],
+            leaf_set: LeafSet::empty(node_id),
+            neighborhood_set: NeighborhoodSet::empty(node_id),
             table_rows,
         };
 
@@ -162,4 +319,75 @@ mod tests {
         let target = Id::from_str("2000-0000-0000-0000").unwrap();
         assert_eq!(table.route(&target), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_repair_entry() {
+        let node_id = Id::from_str("2000-0000-0000-0000").unwrap();
+        let addr = "0.0.0.0:4848".parse().unwrap();
+        let mut table = RoutingTable::empty(node_id);
+        let dead = Peer::raw(Id::from_str("2100-0000-0000-0000").unwrap(),addr);
+        let same_row = Peer::raw(Id::from_str("2200-0000-0000-0000").unwrap(),addr);
+        let deeper_row = Peer::raw(Id::from_str("2020-0000-0000-0000").unwrap(),addr);
+        let upper_row = Peer::raw(Id::from_str("1000-0000-0000-0000").unwrap(),addr);
+        for peer in [dead, same_row, deeper_row, upper_row] {
+            assert!(table.insert(peer));
+        }
+        assert!(!table.insert(Peer::raw(Id::from_str("2110-0000-0000-0000").unwrap(),addr)));
+
+        assert_eq!(table.slot(&dead.id()), Some((1, 1)));
+        table.remove(&dead.id());
+        assert_eq!(table.entry(1, 1), None);
+        assert_eq!(table.repair_contacts(1), vec![same_row, deeper_row]);
+
+        let replacement = Peer::raw(Id::from_str("2110-0000-0000-0000").unwrap(),addr);
+        assert!(table.insert(replacement));
+        assert_eq!(table.route(&Id::from_str("2180-0000-0000-0000").unwrap()), Some(&replacement));
+    }
+
+    #[test]
+    fn test_lower_rtt_replaces_entries() {
+        let node_id = Id::from_str("2000-0000-0000-0000").unwrap();
+        let addr = "0.0.0.0:4848".parse().unwrap();
+        let mut table = RoutingTable::empty(node_id);
+        let far = Peer::raw(Id::from_str("1000-0000-0000-0000").unwrap(),addr);
+        let near = Peer::raw(Id::from_str("1100-0000-0000-0000").unwrap(),addr);
+        let nearest = Peer::raw(Id::from_str("1200-0000-0000-0000").unwrap(),addr);
+        assert!(table.insert(far));
+        assert!(!table.insert(near));
+
+        table.update_rtt(far, Duration::from_millis(30));
+        table.update_rtt(near, Duration::from_millis(20));
+        table.add_neighbor(nearest);
+        assert_eq!(table.entry(0, 1), Some(near));
+        assert_eq!(table.rtt(&near.id()), Some(Duration::from_millis(20)));
+        assert!(!table.insert(nearest));
+
+        table.update_rtt(nearest, Duration::from_millis(10));
+        assert_eq!(table.entry(0, 1), Some(nearest));
+        assert!(!table.insert(far));
+        assert_eq!(table.neighborhood_set().to_vec(), vec![nearest, near, far]);
+        assert!(table.peers().contains(&near));
+
+        table.update_rtt(nearest, Duration::from_millis(130));
+        assert_eq!(table.rtt(&nearest.id()), Some(Duration::from_millis(25)));
+        assert_eq!(table.neighborhood_set().to_vec(), vec![near, nearest, far]);
+    }
+
+    #[test]
+    fn test_broadcast_targets() {
+        let node_id = Id::from_str("2000-0000-0000-0000").unwrap();
+        let addr = "0.0.0.0:4848".parse().unwrap();
+        let mut table = RoutingTable::empty(node_id);
+        let peers = ["1000-0000-0000-0000", "1100-0000-0000-0000", "2100-0000-0000-0000", "2110-0000-0000-0000", "2001-0000-0000-0000"]
+            .map(|id| Peer::raw(Id::from_str(id).unwrap(), addr));
+        table.add_leaves(peers.to_vec());
+        // the routing table entries are preferred to the other peers in their block
+        assert!(table.insert(peers[0]));
+        assert!(table.insert(peers[2]));
+
+        let mut targets = table.broadcast_targets(0);
+        targets.sort_by_key(|(peer, _)| peer.id());
+        assert_eq!(targets, vec![(peers[0], 1), (peers[4], 4), (peers[2], 2)]);
+        assert_eq!(table.broadcast_targets(2), vec![(peers[4], 4)]);
+    }
+}
@@ -18,6 +18,14 @@ impl RoutingTableRow {
             peers: [None; ROW_SIZE]
         }
     }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Option<Peer>> {
+        self.peers.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Option<Peer>> {
+        self.peers.iter_mut()
+    }
 }
 
 impl Index<u8> for RoutingTableRow {
@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::id::Id;
+
+use super::{application::ApplicationId, peer::Peer};
+
+/// How many of the last messages multicast in a tree are remembered to drop their copies.
+const MAX_MULTICASTS: usize = 0x100;
+
+/// The state of this node in the multicast tree of a topic.
+#[derive(Debug, Default)]
+struct Group {
+    /// the next hop towards the root of the topic, None if this node is the root or has lost its parent
+    parent: Option<Peer>,
+    children: Vec<Peer>,
+    /// the local applications subscribed to the topic
+    members: Vec<ApplicationId>,
+    /// the ids of the last messages multicast by this node, the oldest first
+    multicasts: VecDeque<u64>,
+}
+
+impl Group {
+    fn is_empty(&self) -> bool {
+        self.children.is_empty() && self.members.is_empty()
+    }
+}
+
+/// The multicast trees this node is part of, either as a member or as a forwarder.
+/// The tree of a topic is rooted at the node numerically closest to the topic,
+/// each node in the tree knows its parent towards the root and the children it forwards the messages to.
+#[derive(Debug, Default)]
+pub struct Groups {
+    groups: BTreeMap<Id, Group>,
+}
+
+impl Groups {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, topic: &Id) -> bool {
+        self.groups.contains_key(topic)
+    }
+
+    /// Subscribe a local application to the topic.
+    /// Returns true if this node was not part of the tree and must join it.
+    pub fn subscribe(&mut self, topic: Id, application_id: ApplicationId) -> bool {
+        let joined = !self.contains(&topic);
+        let group = self.groups.entry(topic).or_default();
+        if !group.members.contains(&application_id) {
+            group.members.push(application_id);
+        }
+        joined
+    }
+
+    /// Unsubscribe a local application from the topic.
+    /// Returns the parent to notify if this node is no longer needed in the tree.
+    pub fn unsubscribe(&mut self, topic: &Id, application_id: ApplicationId) -> Option<Peer> {
+        let group = self.groups.get_mut(topic)?;
+        group.members.retain(|member| *member != application_id);
+        self.prune(topic)
+    }
+
+    /// Add a child that subscribed to the topic through this node.
+    /// The parent of this node is not accepted as a child, the two nodes would forward the messages to each other,
+    /// it happens when their routing tables disagree on which one is closer to the root.
+    /// Returns true if this node was not part of the tree and must join it.
+    pub fn add_child(&mut self, topic: Id, child: Peer) -> bool {
+        if self.parent(&topic) == Some(child) {
+            return false;
+        }
+        let joined = !self.contains(&topic);
+        let group = self.groups.entry(topic).or_default();
+        if !group.children.contains(&child) {
+            group.children.push(child);
+        }
+        joined
+    }
+
+    /// Remove a child that unsubscribed from the topic.
+    /// Returns the parent to notify if this node is no longer needed in the tree.
+    pub fn remove_child(&mut self, topic: &Id, child: &Peer) -> Option<Peer> {
+        let group = self.groups.get_mut(topic)?;
+        group.children.retain(|peer| peer != child);
+        self.prune(topic)
+    }
+
+    /// Remove the group if this node has no members and no children,
+    /// returning the parent to notify.
+    fn prune(&mut self, topic: &Id) -> Option<Peer> {
+        match self.groups.get(topic) {
+            Some(group) if group.is_empty() => self.groups.remove(topic).and_then(|group| group.parent),
+            _ => None,
+        }
+    }
+
+    /// Set the parent of this node in the tree of the topic, None if this node is the root.
+    /// A child that becomes the parent, because it is now closer to the root, is no longer a child.
+    pub fn set_parent(&mut self, topic: &Id, parent: Option<Peer>) {
+        if let Some(group) = self.groups.get_mut(topic) {
+            if let Some(parent) = parent {
+                group.children.retain(|child| *child != parent);
+            }
+            group.parent = parent;
+        }
+    }
+
+    /// Record a message multicast in the tree of the topic.
+    /// Returns false if this node already multicast it, or if it is not part of the tree.
+    pub fn multicast(&mut self, topic: &Id, message_id: u64) -> bool {
+        let group = match self.groups.get_mut(topic) {
+            Some(group) => group,
+            None => return false,
+        };
+        if group.multicasts.contains(&message_id) {
+            return false;
+        }
+        if group.multicasts.len() == MAX_MULTICASTS {
+            group.multicasts.pop_front();
+        }
+        group.multicasts.push_back(message_id);
+        true
+    }
+
+    pub fn parent(&self, topic: &Id) -> Option<Peer> {
+        self.groups.get(topic).and_then(|group| group.parent)
+    }
+
+    pub fn children(&self, topic: &Id) -> Vec<Peer> {
+        self.groups.get(topic).map(|group| group.children.clone()).unwrap_or_default()
+    }
+
+    pub fn members(&self, topic: &Id) -> Vec<ApplicationId> {
+        self.groups.get(topic).map(|group| group.members.clone()).unwrap_or_default()
+    }
+
+    /// Get the topics where this node has no parent,
+    /// because it is the root or because its parent failed.
+    pub fn orphans(&self) -> Vec<Id> {
+        self.groups.iter().filter(|(_, group)| group.parent.is_none()).map(|(topic, _)| *topic).collect()
+    }
+
+    /// Get the parents and the children of this node in every tree, they are probed like the routing table.
+    pub fn peers(&self) -> Vec<Peer> {
+        let mut peers: Vec<Peer> = Vec::new();
+        for group in self.groups.values() {
+            for peer in group.parent.iter().chain(group.children.iter()) {
+                if !peers.contains(peer) {
+                    peers.push(*peer);
+                }
+            }
+        }
+        peers
+    }
+
+    /// Remove a peer that failed or left from every tree.
+    /// The trees where it was the parent are left without one, they must be joined again.
+    /// Returns the parents to notify for the trees this node is no longer needed in.
+    pub fn remove_peer(&mut self, peer: &Peer) -> Vec<(Id, Peer)> {
+        let topics: Vec<Id> = self.groups.keys().copied().collect();
+        let mut pruned = Vec::new();
+        for topic in topics {
+            let group = self.groups.get_mut(&topic).unwrap();
+            if group.parent == Some(*peer) {
+                group.parent = None;
+            }
+            group.children.retain(|child| child != peer);
+            if let Some(parent) = self.prune(&topic) {
+                pruned.push((topic, parent));
+            }
+        }
+        pruned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune_and_repair() {
+        let mut groups = Groups::new();
+        let topic = Id::from_key("topic");
+        let parent = Peer::new("127.0.0.1:4848".parse().unwrap());
+        let child = Peer::new("127.0.0.1:4849".parse().unwrap());
+
+        assert!(groups.add_child(topic, child));
+        groups.set_parent(&topic, Some(parent));
+        assert!(!groups.subscribe(topic, 1));
+        assert_eq!(groups.peers(), vec![parent, child]);
+
+        // the parent failed, the tree must be joined again
+        assert!(groups.remove_peer(&parent).is_empty());
+        assert_eq!(groups.orphans(), vec![topic]);
+        groups.set_parent(&topic, Some(parent));
+
+        assert_eq!(groups.unsubscribe(&topic, 1), None);
+        assert_eq!(groups.remove_child(&topic, &child), Some(parent));
+        assert!(!groups.contains(&topic));
+    }
+
+    #[test]
+    fn test_no_loop() {
+        let mut groups = Groups::new();
+        let topic = Id::from_key("topic");
+        let parent = Peer::new("127.0.0.1:4848".parse().unwrap());
+
+        // a multicast reaching a node outside the tree is dropped
+        assert!(!groups.multicast(&topic, 0));
+        assert!(groups.subscribe(topic, 1));
+        groups.set_parent(&topic, Some(parent));
+        // the parent subscribing through this node would close a loop
+        assert!(!groups.add_child(topic, parent));
+        assert!(groups.children(&topic).is_empty());
+
+        // a message coming back is not multicast again
+        assert!(groups.multicast(&topic, 0));
+        assert!(!groups.multicast(&topic, 0));
+        for message_id in 1..=MAX_MULTICASTS as u64 {
+            assert!(groups.multicast(&topic, message_id));
+        }
+        assert!(groups.multicast(&topic, 0));
+    }
+}
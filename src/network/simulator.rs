@@ -0,0 +1,178 @@
+use std::{collections::BTreeMap, net::SocketAddr, time::{Duration, Instant}};
+
+use anyhow::bail;
+
+use crate::id::Id;
+
+use super::{config::Config, framework::Framework, transport::simulated::{Link, SimulatedNetwork, SimulatedTransport}};
+
+/// The longest route accepted by check_routing, a longer one is considered a loop.
+const MAX_HOPS: usize = 0x40;
+/// How many times add_node tries to join the network before giving up.
+const JOIN_ATTEMPTS: usize = 5;
+
+/// Runs many nodes in a single thread on the virtual clock of a SimulatedNetwork.
+/// The datagrams are handled in the order they arrive and the maintenance of every node runs
+/// every socket_read_timeout of virtual time, like the threads of a running Framework would do.
+/// Since the nodes are not running, the blocking calls of the Framework (join, put, get, request...)
+/// cannot be used, the simulation drives the joins, the crashes and the routing checks itself.
+#[derive(Debug)]
+pub struct Simulator {
+    network: SimulatedNetwork,
+    nodes: BTreeMap<SocketAddr, Framework<SimulatedTransport>>,
+    config: Config,
+    next_tick: Instant,
+}
+
+impl Simulator {
+    /// Create an empty simulation, the nodes use the given config with their own bind address.
+    pub fn new(seed: u64, default_link: Link, config: Config) -> Self {
+        let network = SimulatedNetwork::new(seed, default_link);
+        let next_tick = network.now() + config.socket_read_timeout;
+        Self {
+            network,
+            nodes: BTreeMap::new(),
+            config,
+            next_tick,
+        }
+    }
+
+    pub fn network(&self) -> &SimulatedNetwork {
+        &self.network
+    }
+
+    pub fn nodes(&self) -> Vec<SocketAddr> {
+        self.nodes.keys().copied().collect()
+    }
+
+    pub fn node(&self, addr: &SocketAddr) -> Option<&Framework<SimulatedTransport>> {
+        self.nodes.get(addr)
+    }
+
+    /// Pick one of the nodes at random.
+    pub fn random_node(&self) -> Option<SocketAddr> {
+        match self.nodes.len() {
+            0 => None,
+            len => self.nodes.keys().nth(self.network.random() as usize % len).copied(),
+        }
+    }
+
+    /// Add a node at addr, the first one bootstraps the network and the others join it through a random node.
+    /// The simulation runs until the join completes, a join that times out is retried through another random node
+    /// because the request may have been lost or routed through a node that crashed.
+    pub fn add_node(&mut self, addr: SocketAddr) -> anyhow::Result<()> {
+        if self.nodes.contains_key(&addr) {
+            bail!("Node {} already exists", addr);
+        }
+        let config = Config { bind_addr: addr, ..self.config.clone() };
+        let framework = Framework::with_transport(self.network.bind(addr)?, config);
+        if self.nodes.is_empty() {
+            framework.bootstrap(addr)?;
+            self.nodes.insert(addr, framework);
+            return Ok(());
+        }
+
+        let entries: Vec<_> = (0..JOIN_ATTEMPTS).filter_map(|_| self.random_node()).collect();
+        self.nodes.insert(addr, framework);
+        for entry in entries.iter() {
+            self.nodes[&addr].start_join(*entry)?;
+            let deadline = self.network.now() + self.config.join_timeout;
+            while self.nodes[&addr].local_peer().is_none() && self.step(deadline) {}
+            if self.nodes[&addr].local_peer().is_some() {
+                return Ok(());
+            }
+        }
+        self.nodes.remove(&addr);
+        bail!("Join of {} timed out through {:?}", addr, entries)
+    }
+
+    /// Remove the node at addr without notifying the other nodes, as if it crashed.
+    pub fn crash_node(&mut self, addr: &SocketAddr) -> anyhow::Result<()> {
+        match self.nodes.remove(addr) {
+            Some(_) => Ok(()),
+            None => bail!("Node {} does not exist", addr),
+        }
+    }
+
+    /// Run the simulation for the given amount of virtual time.
+    pub fn run_for(&mut self, duration: Duration) {
+        let deadline = self.network.now() + duration;
+        while self.step(deadline) {}
+    }
+
+    /// Process the next event, a datagram arriving or the maintenance of the nodes, if it happens before the deadline.
+    /// Returns false, with the clock moved to the deadline, if there is no such event.
+    fn step(&mut self, deadline: Instant) -> bool {
+        match self.network.next_arrival() {
+            Some(arrival) if arrival <= deadline && arrival < self.next_tick => {
+                let addr = self.network.deliver_next();
+                if let Some((addr, node)) = addr.and_then(|addr| self.nodes.get(&addr).map(|node| (addr, node))) {
+                    while self.network.pending(&addr) > 0 {
+                        if let Err(_e) = node.poll() {
+                            // the errors of the nodes are ignored like in the network thread
+                        }
+                    }
+                }
+                true
+            },
+            _ if self.next_tick <= deadline => {
+                self.network.advance(self.next_tick);
+                self.next_tick += self.config.socket_read_timeout;
+                for node in self.nodes.values() {
+                    if let Err(_e) = node.tick() {
+                        // the errors of the nodes are ignored like in the maintenance thread
+                    }
+                }
+                true
+            },
+            _ => {
+                self.network.advance(deadline);
+                false
+            },
+        }
+    }
+
+    /// Follow the route of the key from the node at addr, hop by hop through the routing tables.
+    /// Returns the node the key is delivered to and the number of hops,
+    /// fails if the route reaches a node that does not exist or loops.
+    pub fn route(&self, addr: SocketAddr, key: &Id) -> anyhow::Result<(SocketAddr, usize)> {
+        let mut current = addr;
+        for hops in 0..MAX_HOPS {
+            let node = match self.nodes.get(&current) {
+                Some(node) => node,
+                None => bail!("Key {} routed from {} to {}, which does not exist", key, addr, current),
+            };
+            match node.next_hop(key)? {
+                Some(next_hop) => current = next_hop.addr(),
+                None => return Ok((current, hops)),
+            }
+        }
+        bail!("Key {} routed from {} did not arrive within {} hops", key, addr, MAX_HOPS)
+    }
+
+    /// Get the node numerically closest to the key among the ones in the simulation.
+    pub fn closest_node(&self, key: &Id) -> Option<SocketAddr> {
+        self.nodes.iter()
+            .filter_map(|(addr, node)| node.local_peer().map(|peer| (peer.id().ring_distance(key), *addr)))
+            .min()
+            .map(|(_, addr)| addr)
+    }
+
+    /// Route the given number of random keys from random nodes and check that each one
+    /// is delivered to the node numerically closest to it.
+    pub fn check_routing(&self, keys: usize) -> anyhow::Result<()> {
+        for _ in 0..keys {
+            let key = Id::new(self.network.random().to_be_bytes());
+            let source = match self.random_node() {
+                Some(source) => source,
+                None => bail!("The simulation has no nodes"),
+            };
+            let (destination, hops) = self.route(source, &key)?;
+            let closest = self.closest_node(&key);
+            if Some(destination) != closest {
+                bail!("Key {} routed from {} to {} in {} hops, the closest node is {:?}", key, source, destination, hops, closest);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use std::{collections::BTreeMap, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::id::Id;
+
+/// An operation on the key-value store, executed by the peer numerically closest to the key
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StorageOperation {
+    Put {
+        value: Vec<u8>,
+    },
+    Get,
+    Delete,
+}
+
+/// The result of a StorageOperation, sent back to the peer that requested it
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StorageResult {
+    Stored,
+    Value(Option<Vec<u8>>),
+    Deleted,
+}
+
+/// A stored key with the version of the write that produced it.
+/// A deleted key keeps an entry without value, a tombstone, so that the deletion wins over the older values
+/// still stored by the peers that missed it, a key missing from a peer is only a key it has not received.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Entry {
+    pub value: Option<Vec<u8>>,
+    /// the wall-clock time of the write on the transport in microseconds since the Unix epoch, the newest version wins
+    pub version: u64,
+}
+
+/// How far ahead of the local clock the version of an entry received from another peer can be.
+/// The entries further ahead are rejected, so that a single entry cannot push every later write
+/// of this node to the end of the versions.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(600);
+
+/// The entries of the key-value store kept by this node.
+/// The versions come from the wall-clock time of the transport, given as now.
+#[derive(Debug, Default)]
+pub struct Storage {
+    entries: BTreeMap<Id, Entry>,
+    /// the highest version written or received, the next write gets a higher one even if the clock is behind
+    clock: u64,
+    /// the tombstones older than this version were purged, the older ones are not accepted anymore
+    horizon: u64,
+}
+
+impl Storage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Execute the operation on this node, a put or a delete gets a version newer than every one seen so far.
+    pub fn execute(&mut self, key: Id, operation: StorageOperation, now: SystemTime) -> StorageResult {
+        match operation {
+            StorageOperation::Put { value } => {
+                let version = self.next_version(now);
+                self.entries.insert(key, Entry { value: Some(value), version });
+                StorageResult::Stored
+            },
+            StorageOperation::Get => StorageResult::Value(self.get(&key).cloned()),
+            StorageOperation::Delete => {
+                let version = self.next_version(now);
+                self.entries.insert(key, Entry { value: None, version });
+                StorageResult::Deleted
+            },
+        }
+    }
+
+    fn next_version(&mut self, now: SystemTime) -> u64 {
+        self.clock = self.clock.saturating_add(1).max(micros(now));
+        self.clock
+    }
+
+    /// Keep the entry received from another peer if it is newer than the stored one.
+    /// Returns false if the stored entry is kept, or if the entry is more than MAX_CLOCK_SKEW ahead of now.
+    pub fn merge(&mut self, key: Id, entry: Entry, now: SystemTime) -> bool {
+        if entry.version > now.checked_add(MAX_CLOCK_SKEW).map_or(u64::MAX, micros) {
+            return false;
+        }
+        if entry.value.is_none() && entry.version < self.horizon {
+            return false;
+        }
+        if self.entries.get(&key).is_some_and(|stored| stored.version >= entry.version) {
+            return false;
+        }
+        self.clock = self.clock.max(entry.version);
+        self.entries.insert(key, entry);
+        true
+    }
+
+    /// Forget the key, without leaving a tombstone, once it is handed off to the peers that should store it.
+    pub fn remove(&mut self, key: &Id) {
+        self.entries.remove(key);
+    }
+
+    /// Remove the tombstones older than the timeout.
+    /// A peer that missed a deletion for longer than that may bring the old value back.
+    pub fn purge(&mut self, timeout: Duration, now: SystemTime) {
+        self.horizon = micros(now).saturating_sub(timeout.as_micros() as u64);
+        let horizon = self.horizon;
+        self.entries.retain(|_, entry| entry.value.is_some() || entry.version >= horizon);
+    }
+
+    /// Get the value of the key, None if it is not stored or deleted.
+    pub fn get(&self, key: &Id) -> Option<&Vec<u8>> {
+        self.entries.get(key).and_then(|entry| entry.value.as_ref())
+    }
+
+    /// Get the entry of the key, tombstone included.
+    pub fn entry(&self, key: &Id) -> Option<&Entry> {
+        self.entries.get(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Id, &Entry)> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// The time in microseconds since the Unix epoch.
+fn micros(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |time| u64::try_from(time.as_micros()).unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_keeps_newest() {
+        let mut storage = Storage::new();
+        let now = SystemTime::now();
+        let key = Id::from_key(0);
+        storage.execute(key, StorageOperation::Put { value: vec![1] }, now);
+        let version = storage.entry(&key).unwrap().version;
+
+        assert!(!storage.merge(key, Entry { value: Some(vec![0]), version: version - 1 }, now));
+        assert_eq!(storage.get(&key), Some(&vec![1]));
+        assert!(storage.merge(key, Entry { value: Some(vec![2]), version: version + 1 }, now));
+        assert_eq!(storage.get(&key), Some(&vec![2]));
+
+        // the next write is newer than the entry received, even if the clock has not reached it
+        let ahead = micros(now + MAX_CLOCK_SKEW);
+        assert!(storage.merge(key, Entry { value: Some(vec![3]), version: ahead }, now));
+        storage.execute(key, StorageOperation::Delete, now);
+        assert_eq!(storage.get(&key), None);
+        assert!(!storage.merge(key, Entry { value: Some(vec![3]), version: ahead }, now));
+    }
+
+    #[test]
+    fn test_reject_versions_ahead() {
+        let mut storage = Storage::new();
+        let now = SystemTime::now();
+        let key = Id::from_key(0);
+        assert!(!storage.merge(key, Entry { value: Some(vec![0]), version: u64::MAX }, now));
+        assert_eq!(storage.entry(&key), None);
+
+        // the last version is reached without overflowing, the writes keep it
+        let end = UNIX_EPOCH + Duration::from_micros(u64::MAX);
+        assert!(storage.merge(key, Entry { value: Some(vec![0]), version: u64::MAX }, end));
+        storage.execute(key, StorageOperation::Put { value: vec![1] }, now);
+        assert_eq!(storage.entry(&key), Some(&Entry { value: Some(vec![1]), version: u64::MAX }));
+    }
+
+    #[test]
+    fn test_purge_tombstones() {
+        let mut storage = Storage::new();
+        let now = UNIX_EPOCH + Duration::from_secs(120);
+        let (deleted, stored) = (Id::from_key(0), Id::from_key(1));
+        storage.merge(deleted, Entry { value: None, version: 1 }, now);
+        storage.merge(stored, Entry { value: Some(vec![1]), version: 1 }, now);
+        storage.purge(Duration::from_secs(60), now);
+
+        assert_eq!(storage.entry(&deleted), None);
+        assert_eq!(storage.get(&stored), Some(&vec![1]));
+        // a purged tombstone cannot come back, unlike an old value
+        assert!(!storage.merge(deleted, Entry { value: None, version: 2 }, now));
+        assert!(storage.merge(deleted, Entry { value: Some(vec![2]), version: 2 }, now));
+    }
+}
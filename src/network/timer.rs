@@ -0,0 +1,40 @@
+use std::time::{Duration, Instant};
+
+/// Schedules a periodic task of the maintenance, like the probes or the anti-entropy.
+#[derive(Debug)]
+pub struct Timer {
+    interval: Duration,
+    last_fired: Instant,
+}
+
+impl Timer {
+    /// Create a timer that fires for the first time one interval after now.
+    pub fn new(interval: Duration, now: Instant) -> Self {
+        Self { interval, last_fired: now }
+    }
+
+    /// Check if the interval elapsed since the timer last fired, firing it again if so.
+    pub fn fire(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.last_fired) < self.interval {
+            return false;
+        }
+        self.last_fired = now;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fire() {
+        let now = Instant::now();
+        let mut timer = Timer::new(Duration::from_secs(1), now);
+
+        assert!(!timer.fire(now + Duration::from_millis(500)));
+        assert!(timer.fire(now + Duration::from_millis(1200)));
+        assert!(!timer.fire(now + Duration::from_millis(2000)));
+        assert!(timer.fire(now + Duration::from_millis(2200)));
+    }
+}
@@ -0,0 +1,343 @@
+use std::{collections::{HashMap, HashSet}, net::SocketAddr, sync::{Arc, Mutex}, time::{Duration, Instant, SystemTime}};
+
+use super::{simulated::Rng, Transport};
+
+/// How long a datagram held back to be reordered waits for the next one on its link before being sent anyway.
+pub const MAX_HOLD: Duration = Duration::from_millis(100);
+
+/// The probabilities of the faults injected in the datagrams sent over a link, each fault is drawn independently.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FaultRule {
+    /// the probability for a datagram to be lost
+    pub drop: f64,
+    /// the probability for a datagram to be sent twice
+    pub duplicate: f64,
+    /// the probability for a datagram to be held back and sent after the next one on its link
+    pub reorder: f64,
+    /// the probability for a bit of the datagram to be flipped
+    pub corrupt: f64,
+    /// the probability for a datagram to be delayed by min_delay to max_delay, uniformly distributed
+    pub delay: f64,
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+}
+
+/// What happens to a datagram, drawn from the rule of its link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Plan {
+    /// 0 if the datagram is lost
+    copies: usize,
+    /// the bit to flip, as the index of the byte and the mask
+    corruption: Option<(usize, u8)>,
+    delay: Option<Duration>,
+    reorder: bool,
+}
+
+#[derive(Debug)]
+struct FaultState {
+    rng: Rng,
+    default_rule: FaultRule,
+    links: HashMap<(SocketAddr, SocketAddr), FaultRule>,
+    /// no datagram goes from one set of a partition to the other
+    partitions: Vec<(HashSet<SocketAddr>, HashSet<SocketAddr>)>,
+}
+
+impl FaultState {
+    fn is_partitioned(&self, from: &SocketAddr, to: &SocketAddr) -> bool {
+        self.partitions.iter().any(|(a, b)| (a.contains(from) && b.contains(to)) || (b.contains(from) && a.contains(to)))
+    }
+
+    fn plan(&mut self, from: SocketAddr, to: SocketAddr, len: usize) -> Plan {
+        let rule = self.links.get(&(from, to)).copied().unwrap_or(self.default_rule);
+        if self.is_partitioned(&from, &to) || self.rng.next_f64() < rule.drop {
+            return Plan { copies: 0, corruption: None, delay: None, reorder: false };
+        }
+        let copies = if self.rng.next_f64() < rule.duplicate { 2 } else { 1 };
+        let corruption = match self.rng.next_f64() < rule.corrupt && len > 0 {
+            true => Some((self.rng.next_u64() as usize % len, 1 << (self.rng.next_u64() % 8))),
+            false => None,
+        };
+        let delay = match self.rng.next_f64() < rule.delay {
+            true => Some(self.rng.duration(rule.min_delay, rule.max_delay)),
+            false => None,
+        };
+        let reorder = self.rng.next_f64() < rule.reorder;
+        Plan { copies, corruption, delay, reorder }
+    }
+}
+
+/// The faults injected by the FaultyTransports created from it, they can be changed while the nodes are running.
+/// Every link follows the default rule unless a rule is set for it,
+/// the random choices come from the seed so that a run over a deterministic transport can be replayed.
+#[derive(Debug, Clone)]
+pub struct Faults {
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl Faults {
+    pub fn new(seed: u64, default_rule: FaultRule) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(FaultState {
+                rng: Rng::new(seed),
+                default_rule,
+                links: HashMap::new(),
+                partitions: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn set_default_rule(&self, rule: FaultRule) {
+        self.state.lock().unwrap().default_rule = rule;
+    }
+
+    /// Set the rule of the datagrams sent from one address to another, the opposite direction is not affected.
+    pub fn set_link(&self, from: SocketAddr, to: SocketAddr, rule: FaultRule) {
+        self.state.lock().unwrap().links.insert((from, to), rule);
+    }
+
+    /// Remove the rule of the link, it follows the default rule again.
+    pub fn clear_link(&self, from: SocketAddr, to: SocketAddr) {
+        self.state.lock().unwrap().links.remove(&(from, to));
+    }
+
+    /// Drop every datagram between an address in a and an address in b, in both directions.
+    pub fn partition(&self, a: &[SocketAddr], b: &[SocketAddr]) {
+        let partition = (a.iter().copied().collect(), b.iter().copied().collect());
+        self.state.lock().unwrap().partitions.push(partition);
+    }
+
+    /// Remove every partition.
+    pub fn heal(&self) {
+        self.state.lock().unwrap().partitions.clear();
+    }
+
+    /// Wrap the transport of the node reachable at addr, so that the faults are injected in its datagrams.
+    pub fn wrap<T: Transport>(&self, inner: T, addr: SocketAddr) -> FaultyTransport<T> {
+        FaultyTransport {
+            inner,
+            addr,
+            faults: self.clone(),
+            delayed: Mutex::new(Vec::new()),
+            held: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+/// A transport that injects the faults of a Faults in the datagrams sent through the inner transport.
+/// The delayed datagrams are sent by the first call to send_to or recv_from after their delay,
+/// measured with the clock of the inner transport, and a reordered datagram is sent right after the next one
+/// to the same address, or by the first call after MAX_HOLD if there is none.
+/// The partitions also drop the datagrams received from the other side,
+/// in case the sender does not inject faults.
+#[derive(Debug)]
+pub struct FaultyTransport<T: Transport> {
+    inner: T,
+    addr: SocketAddr,
+    faults: Faults,
+    delayed: Mutex<Vec<(Instant, Vec<u8>, SocketAddr)>>,
+    /// the datagram held back on each link, with the time it was held
+    held: Mutex<HashMap<SocketAddr, (Instant, Vec<u8>)>>,
+}
+
+impl<T: Transport> FaultyTransport<T> {
+    /// Send the delayed datagrams whose delay elapsed, then the held datagrams that waited for MAX_HOLD.
+    fn flush(&self) -> anyhow::Result<()> {
+        let now = self.inner.now();
+        let due: Vec<_> = {
+            let mut delayed = self.delayed.lock().unwrap();
+            let (due, pending) = delayed.drain(..).partition(|(time, _, _)| *time <= now);
+            *delayed = pending;
+            due
+        };
+        for (_, data, addr) in due {
+            self.send_now(&data, addr)?;
+        }
+        let expired: Vec<_> = {
+            let mut held = self.held.lock().unwrap();
+            let addrs: Vec<SocketAddr> = held.iter()
+                .filter(|(_, (since, _))| now.duration_since(*since) >= MAX_HOLD)
+                .map(|(addr, _)| *addr)
+                .collect();
+            addrs.into_iter().filter_map(|addr| held.remove(&addr).map(|(_, data)| (data, addr))).collect()
+        };
+        for (data, addr) in expired {
+            self.inner.send_to(&data, addr)?;
+        }
+        Ok(())
+    }
+
+    /// Send a datagram followed by the one held back on its link, if any.
+    fn send_now(&self, buf: &[u8], addr: SocketAddr) -> anyhow::Result<()> {
+        self.inner.send_to(buf, addr)?;
+        let held = self.held.lock().unwrap().remove(&addr);
+        if let Some((_, data)) = held {
+            self.inner.send_to(&data, addr)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Transport> Transport for FaultyTransport<T> {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> anyhow::Result<()> {
+        self.flush()?;
+        let plan = self.faults.state.lock().unwrap().plan(self.addr, addr, buf.len());
+        let mut data = buf.to_vec();
+        if let Some((index, mask)) = plan.corruption {
+            data[index] ^= mask;
+        }
+        for _ in 0..plan.copies {
+            if let Some(delay) = plan.delay {
+                self.delayed.lock().unwrap().push((self.inner.now() + delay, data.clone(), addr));
+            }
+            else if plan.reorder {
+                let previous = self.held.lock().unwrap().insert(addr, (self.inner.now(), data.clone()));
+                if let Some((_, previous)) = previous {
+                    self.inner.send_to(&previous, addr)?;
+                }
+            }
+            else {
+                self.send_now(&data, addr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> anyhow::Result<(usize, SocketAddr)> {
+        self.flush()?;
+        loop {
+            let (len, addr) = self.inner.recv_from(buf)?;
+            if !self.faults.state.lock().unwrap().is_partitioned(&addr, &self.addr) {
+                return Ok((len, addr));
+            }
+        }
+    }
+
+    fn now(&self) -> Instant {
+        self.inner.now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.inner.system_time()
+    }
+
+    fn random(&self) -> u64 {
+        self.inner.random()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::transport::simulated::{Link, SimulatedNetwork, SimulatedTransport};
+
+    use super::*;
+
+    /// Deliver every datagram in flight and return the ones received by the transport.
+    fn receive_all(network: &SimulatedNetwork, transport: &SimulatedTransport) -> Vec<Vec<u8>> {
+        while network.deliver_next().is_some() {}
+        let mut received = Vec::new();
+        let mut buf = [0; 16];
+        while let Ok((len, _)) = transport.recv_from(&mut buf) {
+            received.push(buf[..len].to_vec());
+        }
+        received
+    }
+
+    #[test]
+    fn test_faults() {
+        let network = SimulatedNetwork::new(42, Link::default());
+        let first_addr = "127.0.0.1:4848".parse().unwrap();
+        let second_addr = "127.0.0.1:4849".parse().unwrap();
+        let faults = Faults::new(42, FaultRule::default());
+        let first = faults.wrap(network.bind(first_addr).unwrap(), first_addr);
+        let second = network.bind(second_addr).unwrap();
+
+        first.send_to(&[1], second_addr).unwrap();
+        assert_eq!(receive_all(&network, &second), vec![vec![1]]);
+
+        faults.set_link(first_addr, second_addr, FaultRule { duplicate: 1.0, ..FaultRule::default() });
+        first.send_to(&[2], second_addr).unwrap();
+        assert_eq!(receive_all(&network, &second), vec![vec![2], vec![2]]);
+
+        faults.set_link(first_addr, second_addr, FaultRule { corrupt: 1.0, ..FaultRule::default() });
+        first.send_to(&[0], second_addr).unwrap();
+        assert_eq!(receive_all(&network, &second)[0][0].count_ones(), 1);
+
+        // the rule of the link overrides the default rule
+        faults.set_default_rule(FaultRule { drop: 1.0, ..FaultRule::default() });
+        faults.clear_link(first_addr, second_addr);
+        first.send_to(&[3], second_addr).unwrap();
+        assert!(receive_all(&network, &second).is_empty());
+    }
+
+    #[test]
+    fn test_delay_and_reorder() {
+        let network = SimulatedNetwork::new(42, Link::default());
+        let first_addr = "127.0.0.1:4848".parse().unwrap();
+        let second_addr = "127.0.0.1:4849".parse().unwrap();
+        let faults = Faults::new(42, FaultRule { reorder: 1.0, ..FaultRule::default() });
+        let first = faults.wrap(network.bind(first_addr).unwrap(), first_addr);
+        let second = network.bind(second_addr).unwrap();
+
+        // each datagram is held back until the next one is sent
+        first.send_to(&[1], second_addr).unwrap();
+        first.send_to(&[2], second_addr).unwrap();
+        assert_eq!(receive_all(&network, &second), vec![vec![1]]);
+
+        faults.set_default_rule(FaultRule { delay: 1.0, min_delay: Duration::from_secs(1), max_delay: Duration::from_secs(1), ..FaultRule::default() });
+        first.send_to(&[3], second_addr).unwrap();
+        assert!(receive_all(&network, &second).is_empty());
+        network.advance(network.now() + Duration::from_secs(1));
+        faults.set_default_rule(FaultRule::default());
+        first.send_to(&[4], second_addr).unwrap();
+        assert_eq!(receive_all(&network, &second), vec![vec![3], vec![2], vec![4]]);
+    }
+
+    #[test]
+    fn test_reorder_per_link() {
+        let network = SimulatedNetwork::new(42, Link::default());
+        let first_addr = "127.0.0.1:4848".parse().unwrap();
+        let second_addr = "127.0.0.1:4849".parse().unwrap();
+        let third_addr = "127.0.0.1:4850".parse().unwrap();
+        let faults = Faults::new(42, FaultRule::default());
+        faults.set_link(first_addr, second_addr, FaultRule { reorder: 1.0, ..FaultRule::default() });
+        let first = faults.wrap(network.bind(first_addr).unwrap(), first_addr);
+        let second = network.bind(second_addr).unwrap();
+        let third = network.bind(third_addr).unwrap();
+
+        // the datagram held on a link is not released by the datagrams sent on another one
+        first.send_to(&[1], second_addr).unwrap();
+        first.send_to(&[2], third_addr).unwrap();
+        assert_eq!(receive_all(&network, &third), vec![vec![2]]);
+        assert!(receive_all(&network, &second).is_empty());
+
+        // with no other datagram on its link, it is sent once it was held for MAX_HOLD
+        network.advance(network.now() + MAX_HOLD);
+        let mut buf = [0; 16];
+        assert!(first.recv_from(&mut buf).is_err());
+        assert_eq!(receive_all(&network, &second), vec![vec![1]]);
+    }
+
+    #[test]
+    fn test_partition() {
+        let network = SimulatedNetwork::new(42, Link::default());
+        let first_addr = "127.0.0.1:4848".parse().unwrap();
+        let second_addr = "127.0.0.1:4849".parse().unwrap();
+        let faults = Faults::new(42, FaultRule::default());
+        let first = faults.wrap(network.bind(first_addr).unwrap(), first_addr);
+        let second = network.bind(second_addr).unwrap();
+
+        faults.partition(&[first_addr], &[second_addr]);
+        first.send_to(&[1], second_addr).unwrap();
+        assert!(receive_all(&network, &second).is_empty());
+        // the datagrams from the other side are dropped even if the sender does not inject faults
+        second.send_to(&[1], first_addr).unwrap();
+        while network.deliver_next().is_some() {}
+        let mut buf = [0; 16];
+        assert!(first.recv_from(&mut buf).is_err());
+
+        faults.heal();
+        second.send_to(&[2], first_addr).unwrap();
+        while network.deliver_next().is_some() {}
+        assert_eq!(first.recv_from(&mut buf).unwrap(), (1, second_addr));
+    }
+}
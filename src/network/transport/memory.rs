@@ -0,0 +1,96 @@
+use std::{collections::HashMap, net::SocketAddr, sync::{mpsc, Arc, Mutex}, time::Duration};
+
+use anyhow::bail;
+
+use super::Transport;
+
+type Mailbox = mpsc::Sender<(Vec<u8>, SocketAddr)>;
+
+/// Connects the MemoryTransports of the nodes running in the same process,
+/// the datagrams are moved through channels instead of sockets.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryNetwork {
+    mailboxes: Arc<Mutex<HashMap<SocketAddr, Mailbox>>>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a transport reachable at addr, the address is released when the transport is dropped.
+    pub fn bind(&self, addr: SocketAddr, read_timeout: Duration) -> anyhow::Result<MemoryTransport> {
+        let mut mailboxes = self.mailboxes.lock().unwrap();
+        if mailboxes.contains_key(&addr) {
+            bail!("Address {} is already in use", addr);
+        }
+        let (sender, receiver) = mpsc::channel();
+        mailboxes.insert(addr, sender);
+        Ok(MemoryTransport {
+            addr,
+            network: self.clone(),
+            receiver: Mutex::new(receiver),
+            read_timeout,
+        })
+    }
+}
+
+/// A transport that delivers the datagrams to the other transports of the same MemoryNetwork.
+/// Like UDP, the datagrams sent to an address that is not bound are dropped.
+#[derive(Debug)]
+pub struct MemoryTransport {
+    addr: SocketAddr,
+    network: MemoryNetwork,
+    receiver: Mutex<mpsc::Receiver<(Vec<u8>, SocketAddr)>>,
+    read_timeout: Duration,
+}
+
+impl Transport for MemoryTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> anyhow::Result<()> {
+        if let Some(mailbox) = self.network.mailboxes.lock().unwrap().get(&addr) {
+            // the receiver is gone if the transport is being dropped, the datagram is lost like in UDP
+            let _ = mailbox.send((buf.to_vec(), self.addr));
+        }
+        Ok(())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> anyhow::Result<(usize, SocketAddr)> {
+        let (datagram, addr) = self.receiver.lock().unwrap().recv_timeout(self.read_timeout)?;
+        // like in UDP, the part of the datagram that does not fit in the buffer is discarded
+        let len = datagram.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram[..len]);
+        Ok((len, addr))
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.network.mailboxes.lock().unwrap().remove(&self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_and_recv() {
+        let network = MemoryNetwork::new();
+        let first_addr = "127.0.0.1:4848".parse().unwrap();
+        let second_addr = "127.0.0.1:4849".parse().unwrap();
+        let first = network.bind(first_addr, Duration::from_millis(10)).unwrap();
+        let second = network.bind(second_addr, Duration::from_millis(10)).unwrap();
+        assert!(network.bind(first_addr, Duration::from_millis(10)).is_err());
+
+        first.send_to(&[1, 2, 3], second_addr).unwrap();
+        let mut buf = [0; 2];
+        assert_eq!(second.recv_from(&mut buf).unwrap(), (2, first_addr));
+        assert_eq!(buf, [1, 2]);
+        assert!(second.recv_from(&mut buf).is_err());
+
+        // the address is released when the transport is dropped
+        drop(second);
+        first.send_to(&[1], second_addr).unwrap();
+        assert!(network.bind(second_addr, Duration::from_millis(10)).is_ok());
+    }
+}
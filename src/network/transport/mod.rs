@@ -0,0 +1,7 @@
+pub mod udp;
+pub mod memory;
+pub mod simulated;
+pub mod faulty;
+#[allow(clippy::module_inception)]
+mod transport;
+pub use transport::Transport;
@@ -0,0 +1,299 @@
+use std::{collections::{BTreeMap, HashMap, VecDeque}, net::SocketAddr, sync::{Arc, Mutex}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use anyhow::bail;
+
+use super::Transport;
+
+/// A seeded pseudo-random generator (SplitMix64), so that a simulation can be replayed from its seed.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Get a number uniformly distributed in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Get a duration uniformly distributed in [min, max].
+    pub fn duration(&mut self, min: Duration, max: Duration) -> Duration {
+        match max.checked_sub(min) {
+            Some(spread) if !spread.is_zero() => min + spread.mul_f64(self.next_f64()),
+            _ => min,
+        }
+    }
+}
+
+/// The behaviour of the link between two virtual addresses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Link {
+    /// the datagrams take between min_latency and max_latency to arrive, uniformly distributed
+    pub min_latency: Duration,
+    pub max_latency: Duration,
+    /// the probability for a datagram to be lost
+    pub loss: f64,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self {
+            min_latency: Duration::from_millis(1),
+            max_latency: Duration::from_millis(1),
+            loss: 0.0,
+        }
+    }
+}
+
+/// The datagrams sent, delivered and lost in a simulation.
+/// The datagrams sent to an address that is not bound are lost.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub sent: u64,
+    pub delivered: u64,
+    pub lost: u64,
+}
+
+/// A datagram sent in a simulation, at the given time since the simulation started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentDatagram {
+    pub time: Duration,
+    pub from: SocketAddr,
+    pub to: SocketAddr,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Datagram {
+    data: Vec<u8>,
+    from: SocketAddr,
+    to: SocketAddr,
+}
+
+#[derive(Debug)]
+struct State {
+    start: Instant,
+    now: Instant,
+    rng: Rng,
+    default_link: Link,
+    links: HashMap<(SocketAddr, SocketAddr), Link>,
+    /// the datagrams in flight by arrival time, ties are broken by the order they were sent in
+    in_flight: BTreeMap<(Instant, u64), Datagram>,
+    next_sequence: u64,
+    inboxes: HashMap<SocketAddr, VecDeque<(Vec<u8>, SocketAddr)>>,
+    stats: Stats,
+    /// the datagrams sent since the trace was enabled, None if it is not
+    trace: Option<Vec<SentDatagram>>,
+}
+
+/// Connects the SimulatedTransports of a simulation on a virtual clock.
+/// The clock only moves when the datagrams are delivered or when it is advanced explicitly,
+/// and every random choice comes from the seed, so the same seed and the same calls give the same run.
+#[derive(Debug, Clone)]
+pub struct SimulatedNetwork {
+    state: Arc<Mutex<State>>,
+}
+
+impl SimulatedNetwork {
+    /// Create a network where every link behaves like default_link unless set otherwise.
+    pub fn new(seed: u64, default_link: Link) -> Self {
+        let now = Instant::now();
+        Self {
+            state: Arc::new(Mutex::new(State {
+                start: now,
+                now,
+                rng: Rng::new(seed),
+                default_link,
+                links: HashMap::new(),
+                in_flight: BTreeMap::new(),
+                next_sequence: 0,
+                inboxes: HashMap::new(),
+                stats: Stats::default(),
+                trace: None,
+            })),
+        }
+    }
+
+    /// Set the behaviour of the link between a and b, in both directions.
+    pub fn set_link(&self, a: SocketAddr, b: SocketAddr, link: Link) {
+        let mut state = self.state.lock().unwrap();
+        state.links.insert((a, b), link);
+        state.links.insert((b, a), link);
+    }
+
+    /// Create a transport reachable at addr, the address is released when the transport is dropped.
+    pub fn bind(&self, addr: SocketAddr) -> anyhow::Result<SimulatedTransport> {
+        let mut state = self.state.lock().unwrap();
+        if state.inboxes.contains_key(&addr) {
+            bail!("Address {} is already in use", addr);
+        }
+        state.inboxes.insert(addr, VecDeque::new());
+        Ok(SimulatedTransport { addr, network: self.clone() })
+    }
+
+    /// Get the current time of the virtual clock.
+    pub fn now(&self) -> Instant {
+        self.state.lock().unwrap().now
+    }
+
+    /// Get the wall-clock time of the virtual clock, the simulation starts at the Unix epoch.
+    pub fn system_time(&self) -> SystemTime {
+        let state = self.state.lock().unwrap();
+        UNIX_EPOCH + (state.now - state.start)
+    }
+
+    /// Move the virtual clock forward to time, it never moves backwards.
+    pub fn advance(&self, time: Instant) {
+        let mut state = self.state.lock().unwrap();
+        state.now = state.now.max(time);
+    }
+
+    /// Get the next number from the random generator of the simulation.
+    pub fn random(&self) -> u64 {
+        self.state.lock().unwrap().rng.next_u64()
+    }
+
+    /// Get the arrival time of the next datagram in flight.
+    pub fn next_arrival(&self) -> Option<Instant> {
+        self.state.lock().unwrap().in_flight.keys().next().map(|(time, _)| *time)
+    }
+
+    /// Move the clock to the arrival time of the next datagram in flight and deliver it.
+    /// Returns the address it was sent to, whose transport can now receive it.
+    pub fn deliver_next(&self) -> Option<SocketAddr> {
+        let mut state = self.state.lock().unwrap();
+        let ((time, _), datagram) = state.in_flight.pop_first()?;
+        state.now = state.now.max(time);
+        match state.inboxes.get_mut(&datagram.to) {
+            Some(inbox) => {
+                inbox.push_back((datagram.data, datagram.from));
+                state.stats.delivered += 1;
+            },
+            None => state.stats.lost += 1,
+        }
+        Some(datagram.to)
+    }
+
+    /// Get how many datagrams the transport at addr can receive.
+    pub fn pending(&self, addr: &SocketAddr) -> usize {
+        self.state.lock().unwrap().inboxes.get(addr).map_or(0, |inbox| inbox.len())
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// Record every datagram sent from now on, lost ones included, to compare two runs of a simulation.
+    pub fn enable_trace(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.trace.is_none() {
+            state.trace = Some(Vec::new());
+        }
+    }
+
+    /// Get the datagrams sent since the trace was enabled, in the order they were sent.
+    pub fn trace(&self) -> Vec<SentDatagram> {
+        self.state.lock().unwrap().trace.clone().unwrap_or_default()
+    }
+}
+
+/// A transport that sends the datagrams through a SimulatedNetwork.
+/// Receiving never blocks, it fails immediately if no datagram was delivered to this transport.
+#[derive(Debug)]
+pub struct SimulatedTransport {
+    addr: SocketAddr,
+    network: SimulatedNetwork,
+}
+
+impl Transport for SimulatedTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> anyhow::Result<()> {
+        let mut state = self.network.state.lock().unwrap();
+        let state = &mut *state;
+        state.stats.sent += 1;
+        let time = state.now - state.start;
+        if let Some(trace) = state.trace.as_mut() {
+            trace.push(SentDatagram { time, from: self.addr, to: addr, data: buf.to_vec() });
+        }
+        let link = state.links.get(&(self.addr, addr)).copied().unwrap_or(state.default_link);
+        if state.rng.next_f64() < link.loss {
+            state.stats.lost += 1;
+            return Ok(());
+        }
+        let arrival = state.now + state.rng.duration(link.min_latency, link.max_latency);
+        let sequence = state.next_sequence;
+        state.next_sequence += 1;
+        state.in_flight.insert((arrival, sequence), Datagram { data: buf.to_vec(), from: self.addr, to: addr });
+        Ok(())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> anyhow::Result<(usize, SocketAddr)> {
+        let datagram = self.network.state.lock().unwrap().inboxes.get_mut(&self.addr).and_then(|inbox| inbox.pop_front());
+        let (datagram, addr) = match datagram {
+            Some(datagram) => datagram,
+            None => bail!("No datagram delivered to {}", self.addr),
+        };
+        // like in UDP, the part of the datagram that does not fit in the buffer is discarded
+        let len = datagram.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram[..len]);
+        Ok((len, addr))
+    }
+
+    fn now(&self) -> Instant {
+        self.network.now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.network.system_time()
+    }
+
+    fn random(&self) -> u64 {
+        self.network.random()
+    }
+}
+
+impl Drop for SimulatedTransport {
+    fn drop(&mut self) {
+        self.network.state.lock().unwrap().inboxes.remove(&self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_and_loss() {
+        let network = SimulatedNetwork::new(42, Link { min_latency: Duration::from_millis(10), max_latency: Duration::from_millis(20), loss: 0.0 });
+        let first_addr = "127.0.0.1:4848".parse().unwrap();
+        let second_addr = "127.0.0.1:4849".parse().unwrap();
+        let first = network.bind(first_addr).unwrap();
+        let second = network.bind(second_addr).unwrap();
+        let start = network.now();
+
+        first.send_to(b"hello", second_addr).unwrap();
+        let mut buf = [0; 16];
+        assert!(second.recv_from(&mut buf).is_err());
+        assert_eq!(network.deliver_next(), Some(second_addr));
+        let elapsed = network.now() - start;
+        assert!(elapsed >= Duration::from_millis(10) && elapsed <= Duration::from_millis(20));
+        assert_eq!(second.recv_from(&mut buf).unwrap(), (5, first_addr));
+
+        // a lossy link drops every datagram
+        network.set_link(first_addr, second_addr, Link { loss: 1.0, ..Link::default() });
+        second.send_to(b"hello", first_addr).unwrap();
+        assert_eq!(network.next_arrival(), None);
+        assert_eq!(network.stats(), Stats { sent: 2, delivered: 1, lost: 1 });
+    }
+}
@@ -0,0 +1,33 @@
+use std::{fmt::Debug, net::SocketAddr, time::{Instant, SystemTime}};
+
+/// Sends and receives the datagrams of a Network.
+/// The peers are reached through their SocketAddr, whatever the transport, because the id of a peer is derived from it.
+pub trait Transport: Debug + Send + Sync + 'static {
+    /// Send a datagram to the peer at addr, the delivery is not guaranteed.
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> anyhow::Result<()>;
+
+    /// Wait for the next datagram, copy it in buf and return its length and the address of the sender.
+    /// Fails if no datagram arrives within the read timeout of the transport,
+    /// so that the receiving thread can notice when the framework is stopped.
+    fn recv_from(&self, buf: &mut [u8]) -> anyhow::Result<(usize, SocketAddr)>;
+
+    /// The current time, every timeout of the Network is measured with it.
+    /// A simulated transport can run the Network on a virtual clock.
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// The current wall-clock time, the entries of the key-value store are versioned with it
+    /// so that the versions written on different nodes can be compared.
+    /// A simulated transport derives it from its virtual clock.
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    /// A random number, the ids of the messages, requests and probes of a Network start from one
+    /// so that the responses to a previous run are not matched.
+    /// A simulated transport draws it from the seed of the simulation.
+    fn random(&self) -> u64 {
+        std::hash::BuildHasher::hash_one(&std::hash::RandomState::new(), Instant::now())
+    }
+}
@@ -0,0 +1,14 @@
+use std::net::{SocketAddr, UdpSocket};
+
+use super::Transport;
+
+impl Transport for UdpSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> anyhow::Result<()> {
+        UdpSocket::send_to(self, buf, addr)?;
+        Ok(())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> anyhow::Result<(usize, SocketAddr)> {
+        Ok(UdpSocket::recv_from(self, buf)?)
+    }
+}
@@ -0,0 +1,127 @@
+use std::{hash::{DefaultHasher, Hash, Hasher}, net::SocketAddr, sync::{mpsc, Arc}, thread};
+
+use anyhow::bail;
+
+use super::packet::Packet;
+
+/// Handles the received packets on a pool of threads, so that a slow handler does not stall the receiving.
+/// The packets from the same source always go to the same worker and are handled in the order they were received.
+/// Each worker has a bounded queue, the packets for a worker whose queue is full are dropped,
+/// like a full socket buffer would do, instead of blocking the receiving thread.
+/// The workers stop once the pool is dropped and their queues are empty.
+#[derive(Debug)]
+pub struct WorkerPool {
+    queues: Vec<mpsc::SyncSender<(Packet, SocketAddr)>>,
+}
+
+impl WorkerPool {
+    /// Spawn count workers with a queue of queue_size packets each, handle is called on the worker threads.
+    /// Returns the pool and the handles of the worker threads.
+    /// Fails if queue_size is 0, a worker could only take the packets sent while it is waiting for one.
+    pub fn new<F>(count: usize, queue_size: usize, handle: F) -> anyhow::Result<(Self, Vec<thread::JoinHandle<()>>)>
+    where
+        F: Fn(Packet, SocketAddr) + Send + Sync + 'static,
+    {
+        if queue_size == 0 {
+            bail!("The queue of the workers must hold at least one packet");
+        }
+        let handle = Arc::new(handle);
+        let mut queues = Vec::new();
+        let mut threads = Vec::new();
+        for i in 0..count {
+            let (sender, receiver) = mpsc::sync_channel::<(Packet, SocketAddr)>(queue_size);
+            let handle = handle.clone();
+            threads.push(thread::Builder::new().name(format!("worker-{}", i)).spawn(move || {
+                for (packet, addr) in receiver {
+                    handle(packet, addr);
+                }
+            })?);
+            queues.push(sender);
+        }
+        Ok((Self { queues }, threads))
+    }
+
+    /// Queue the packet received from addr on the worker of its source.
+    /// Returns false if the packet was dropped because the queue is full or there are no workers.
+    pub fn dispatch(&self, packet: Packet, addr: SocketAddr) -> bool {
+        if self.queues.is_empty() {
+            return false;
+        }
+        let mut hasher = DefaultHasher::new();
+        addr.hash(&mut hasher);
+        let worker = (hasher.finish() % self.queues.len() as u64) as usize;
+        self.queues[worker].try_send((packet, addr)).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Barrier, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn test_ordered_per_source() {
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let (pool, threads) = {
+            let handled = handled.clone();
+            WorkerPool::new(4, 0x100, move |packet, addr| {
+                if let Packet::Ping { nonce } = packet {
+                    handled.lock().unwrap().push((addr, nonce));
+                }
+            }).unwrap()
+        };
+        let sources: Vec<SocketAddr> = (0..8).map(|i| format!("127.0.0.1:{}", 4848 + i).parse().unwrap()).collect();
+        for nonce in 0..0x20 {
+            for addr in sources.iter() {
+                assert!(pool.dispatch(Packet::Ping { nonce }, *addr));
+            }
+        }
+        drop(pool);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let handled = handled.lock().unwrap();
+        assert_eq!(handled.len(), 0x20 * sources.len());
+        for addr in sources.iter() {
+            let nonces: Vec<u64> = handled.iter().filter(|(source, _)| source == addr).map(|(_, nonce)| *nonce).collect();
+            assert_eq!(nonces, (0..0x20).collect::<Vec<u64>>());
+        }
+    }
+
+    #[test]
+    fn test_full_queue_drops() {
+        // the only worker tells when it takes a packet, then it is stuck on it until the barrier is reached
+        let barrier = Arc::new(Barrier::new(2));
+        let (taken, taken_receiver) = mpsc::channel();
+        let (pool, threads) = {
+            let barrier = barrier.clone();
+            let taken = Mutex::new(taken);
+            WorkerPool::new(1, 2, move |_, _| {
+                taken.lock().unwrap().send(()).unwrap();
+                barrier.wait();
+            }).unwrap()
+        };
+        let addr = "127.0.0.1:4848".parse().unwrap();
+        assert!(pool.dispatch(Packet::Ping { nonce: 0 }, addr));
+        // wait for the worker to take the first packet out of the queue
+        taken_receiver.recv().unwrap();
+        assert!(pool.dispatch(Packet::Ping { nonce: 1 }, addr));
+        assert!(pool.dispatch(Packet::Ping { nonce: 2 }, addr));
+        assert!(!pool.dispatch(Packet::Ping { nonce: 3 }, addr));
+
+        drop(pool);
+        for _ in 0..3 {
+            barrier.wait();
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_empty_queue_rejected() {
+        assert!(WorkerPool::new(1, 0, |_, _| {}).is_err());
+    }
+}
@@ -0,0 +1,903 @@
+use std::{net::{SocketAddr, UdpSocket}, sync::{mpsc, Mutex}, time::{Duration, Instant}};
+
+use cactus::{id::Id, network::{application::{Application, LeafSetChange}, config::Config, framework::Framework, packet::Packet, peer::Peer, simulator::Simulator, storage::Entry, transport::{faulty::{FaultRule, Faults}, memory::MemoryNetwork, simulated::{Link, Stats}, Transport}}};
+
+fn config(port: u16) -> Config {
+    let bind_addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+    Config {
+        bind_addr,
+        entry_addr: bind_addr,
+        socket_read_timeout: Duration::from_millis(10),
+        socket_write_timeout: Duration::from_millis(10),
+        join_timeout: Duration::from_secs(2),
+        request_timeout: Duration::from_secs(1),
+        replication_factor: 3,
+        probe_interval: Duration::from_millis(100),
+        probe_timeout: Duration::from_millis(100),
+        max_missed_probes: 3,
+        dead_peer_timeout: Duration::from_secs(10),
+        anti_entropy_interval: Duration::from_millis(500),
+        tombstone_timeout: Duration::from_secs(60),
+        retransmission_timeout: Duration::from_millis(100),
+        max_retransmissions: 3,
+        duplicate_timeout: Duration::from_secs(10),
+        reassembly_timeout: Duration::from_secs(1),
+        max_reassembly_bytes: 0x100000,
+        workers: 4,
+        worker_queue_size: 0x100,
+    }
+}
+
+/// (node that received the message, key, payload)
+type Delivery = (usize, Id, Vec<u8>);
+
+struct Collector {
+    node: usize,
+    delivered: Mutex<mpsc::Sender<Delivery>>,
+}
+
+impl Application for Collector {
+    fn deliver(&self, key: Id, payload: Vec<u8>) {
+        self.delivered.lock().unwrap().send((self.node, key, payload)).unwrap();
+    }
+
+    fn request(&self, _key: Id, payload: Vec<u8>, _origin: &Peer) -> Option<Vec<u8>> {
+        let mut response = vec![self.node as u8];
+        response.extend(payload);
+        Some(response)
+    }
+}
+
+fn closest(ports: std::ops::Range<u16>, key: &Id) -> usize {
+    ports.map(|port| Peer::new(config(port).bind_addr).id().ring_distance(key))
+        .enumerate()
+        .min_by_key(|(_, distance)| *distance)
+        .unwrap().0
+}
+
+fn start_network(ports: std::ops::Range<u16>) -> (Vec<Framework>, mpsc::Receiver<Delivery>) {
+    start_network_with(ports, |port| Framework::new(config(port)).unwrap())
+}
+
+fn start_network_with<T: Transport>(ports: std::ops::Range<u16>, new: impl Fn(u16) -> Framework<T>) -> (Vec<Framework<T>>, mpsc::Receiver<Delivery>) {
+    let (sender, receiver) = mpsc::channel();
+    let mut frameworks = Vec::new();
+    for (node, port) in ports.clone().enumerate() {
+        let mut framework = new(port);
+        framework.register_application(1, Collector { node, delivered: Mutex::new(sender.clone()) }).unwrap();
+        framework.start().unwrap();
+        if frameworks.is_empty() {
+            framework.bootstrap(config(port).bind_addr).unwrap();
+        }
+        else {
+            framework.join(config(ports.start).bind_addr).unwrap();
+            // join returns before the announces reach the other peers, wait for them to route to the new one
+            let peer = framework.local_peer().unwrap();
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_secs(1) && !frameworks.iter().all(|other: &Framework<T>| other.next_hop(&peer.id()).unwrap() == Some(peer)) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+        frameworks.push(framework);
+    }
+    (frameworks, receiver)
+}
+
+#[test]
+fn test_join_and_deliver() {
+    let ports = 47100..47108;
+    let (frameworks, receiver) = start_network(ports.clone());
+
+    for i in 0..32 {
+        let key = Id::from_key(i);
+        frameworks[i % frameworks.len()].send(1, key, vec![i as u8]).unwrap();
+        let (node, delivered_key, payload) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(node, closest(ports.clone(), &key));
+        assert_eq!(delivered_key, key);
+        assert_eq!(payload, vec![i as u8]);
+    }
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_application_ids() {
+    let ports = 47280..47288;
+    let (frameworks, first) = start_network(ports.clone());
+    let (sender, second) = mpsc::channel();
+    for (node, framework) in frameworks.iter().enumerate() {
+        framework.register_application(2, Collector { node, delivered: Mutex::new(sender.clone()) }).unwrap();
+    }
+
+    // each application only receives the messages sent to its id
+    for i in 0..16 {
+        let key = Id::from_key(i);
+        frameworks[i % frameworks.len()].send(1, key, vec![1, i as u8]).unwrap();
+        frameworks[i % frameworks.len()].send(2, key, vec![2, i as u8]).unwrap();
+        assert_eq!(first.recv_timeout(Duration::from_secs(1)).unwrap().2, vec![1, i as u8]);
+        assert_eq!(second.recv_timeout(Duration::from_secs(1)).unwrap().2, vec![2, i as u8]);
+    }
+    assert!(first.recv_timeout(Duration::from_millis(100)).is_err());
+    assert!(second.recv_timeout(Duration::from_millis(100)).is_err());
+
+    // the nodes where the application is not registered forward its messages unchanged
+    let key = Id::from_key(0);
+    let root = closest(ports.clone(), &key);
+    // the sender is kept so that the channel stays open once the application is unregistered
+    let (sender, third) = mpsc::channel();
+    frameworks[root].register_application(3, Collector { node: root, delivered: Mutex::new(sender.clone()) }).unwrap();
+    for node in (0..frameworks.len()).filter(|node| *node != root) {
+        frameworks[node].send(3, key, vec![node as u8]).unwrap();
+        assert_eq!(third.recv_timeout(Duration::from_secs(1)).unwrap(), (root, key, vec![node as u8]));
+    }
+
+    // the root drops the messages of an application it does not run
+    frameworks[root].unregister_application(3).unwrap();
+    assert!(frameworks[root].send(3, key, vec![0]).is_err());
+    for node in (0..frameworks.len()).filter(|node| *node != root) {
+        frameworks[node].send(3, key, vec![node as u8]).unwrap();
+    }
+    assert!(third.recv_timeout(Duration::from_millis(200)).is_err());
+    assert!(first.recv_timeout(Duration::from_millis(100)).is_err());
+    assert!(second.recv_timeout(Duration::from_millis(100)).is_err());
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_leave() {
+    let ports = 47110..47118;
+    let (mut frameworks, receiver) = start_network(ports.clone());
+    let mut left = frameworks.pop().unwrap();
+    left.leave().unwrap();
+    // let the other peers handle the Leaving packets
+    std::thread::sleep(Duration::from_millis(100));
+    let remaining = ports.start..ports.end - 1;
+
+    for i in 0..32 {
+        let key = Id::from_key(i);
+        frameworks[i % frameworks.len()].send(1, key, vec![i as u8]).unwrap();
+        let (node, delivered_key, _) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(node, closest(remaining.clone(), &key));
+        assert_eq!(delivered_key, key);
+    }
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_leave_not_running() {
+    let port = 47290;
+    let mut framework = Framework::new(config(port)).unwrap();
+    assert!(framework.join(config(port + 1).bind_addr).is_err());
+    framework.bootstrap(config(port).bind_addr).unwrap();
+    assert!(framework.leave().is_err());
+    // the node is still part of its network
+    assert_eq!(framework.local_peer(), Some(Peer::new(config(port).bind_addr)));
+}
+
+#[test]
+fn test_empty_worker_queue() {
+    let port = 47291;
+    let mut framework = Framework::new(Config { worker_queue_size: 0, ..config(port) }).unwrap();
+    assert!(framework.start().is_err());
+    // the failed start left the framework stopped
+    framework.bootstrap(config(port).bind_addr).unwrap();
+    assert!(framework.leave().is_err());
+}
+
+/// Appends the index of the node to the payload of every message it forwards.
+struct Tagger {
+    node: usize,
+    delivered: Mutex<mpsc::Sender<Vec<u8>>>,
+}
+
+impl Application for Tagger {
+    fn deliver(&self, _key: Id, payload: Vec<u8>) {
+        self.delivered.lock().unwrap().send(payload).unwrap();
+    }
+
+    fn forward(&self, _key: Id, mut payload: Vec<u8>, _next_hop: &Peer) -> Option<Vec<u8>> {
+        payload.push(self.node as u8);
+        Some(payload)
+    }
+}
+
+/// Drops every message it forwards.
+struct Dropper {
+    delivered: Mutex<mpsc::Sender<Vec<u8>>>,
+}
+
+impl Application for Dropper {
+    fn deliver(&self, _key: Id, payload: Vec<u8>) {
+        self.delivered.lock().unwrap().send(payload).unwrap();
+    }
+
+    fn forward(&self, _key: Id, _payload: Vec<u8>, _next_hop: &Peer) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+#[test]
+fn test_forward() {
+    let ports = 47260..47268;
+    let (frameworks, _) = start_network(ports.clone());
+    let (tagged_sender, tagged) = mpsc::channel();
+    let (dropped_sender, dropped) = mpsc::channel();
+    for (node, framework) in frameworks.iter().enumerate() {
+        framework.register_application(2, Tagger { node, delivered: Mutex::new(tagged_sender.clone()) }).unwrap();
+        framework.register_application(3, Dropper { delivered: Mutex::new(dropped_sender.clone()) }).unwrap();
+    }
+
+    for i in 0..16 {
+        let key = Id::from_key(i);
+        let node = i % frameworks.len();
+        frameworks[node].send(2, key, vec![0xff]).unwrap();
+        frameworks[node].send(3, key, vec![0xff]).unwrap();
+        let payload = tagged.recv_timeout(Duration::from_secs(1)).unwrap();
+        // the root delivers without forwarding, the sender is the first node to forward otherwise
+        match closest(ports.clone(), &key) == node {
+            true => {
+                assert_eq!(payload, vec![0xff]);
+                assert_eq!(dropped.recv_timeout(Duration::from_secs(1)).unwrap(), vec![0xff]);
+            },
+            false => {
+                assert_eq!(payload[..2], [0xff, node as u8]);
+                assert!(dropped.recv_timeout(Duration::from_millis(100)).is_err());
+            },
+        }
+    }
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+/// Records the changes of the leaf set it is notified of.
+struct Observer {
+    changes: Mutex<mpsc::Sender<LeafSetChange>>,
+}
+
+impl Application for Observer {
+    fn deliver(&self, _key: Id, _payload: Vec<u8>) {}
+
+    fn update(&self, change: LeafSetChange) {
+        self.changes.lock().unwrap().send(change).unwrap();
+    }
+}
+
+#[test]
+fn test_update() {
+    let ports = 47270..47272;
+    let (sender, changes) = mpsc::channel();
+    let mut first = Framework::new(config(ports.start)).unwrap();
+    first.register_application(1, Observer { changes: Mutex::new(sender) }).unwrap();
+    first.start().unwrap();
+    first.bootstrap(config(ports.start).bind_addr).unwrap();
+
+    let mut second = Framework::new(config(ports.start + 1)).unwrap();
+    second.start().unwrap();
+    second.join(config(ports.start).bind_addr).unwrap();
+    let peer = Peer::new(config(ports.start + 1).bind_addr);
+    assert_eq!(changes.recv_timeout(Duration::from_secs(1)).unwrap(), LeafSetChange::Joined(peer));
+
+    second.leave().unwrap();
+    assert_eq!(changes.recv_timeout(Duration::from_secs(1)).unwrap(), LeafSetChange::Left(peer));
+    assert!(changes.recv_timeout(Duration::from_millis(100)).is_err());
+
+    first.stop().unwrap();
+}
+
+#[test]
+fn test_storage() {
+    let ports = 47120..47128;
+    let (frameworks, _) = start_network(ports.clone());
+
+    for i in 0..32 {
+        let key = Id::from_key(i);
+        frameworks[i % frameworks.len()].put(key, vec![i as u8]).unwrap();
+    }
+    for i in 0..32 {
+        let key = Id::from_key(i);
+        let framework = &frameworks[(i + 3) % frameworks.len()];
+        assert_eq!(framework.get(key).unwrap(), Some(vec![i as u8]));
+        framework.delete(key).unwrap();
+        assert_eq!(framework.get(key).unwrap(), None);
+    }
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_storage_timeout() {
+    let ports = 47130..47132;
+    let (mut frameworks, _) = start_network(ports.clone());
+    frameworks.pop().unwrap().stop().unwrap();
+
+    let key = (0..).map(Id::from_key).find(|key| closest(ports.clone(), key) == 1).unwrap();
+    assert!(frameworks[0].get(key).is_err());
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_replication() {
+    let ports = 47140..47148;
+    let (mut frameworks, _) = start_network(ports.clone());
+
+    let keys: Vec<Id> = (0..32).map(Id::from_key).collect();
+    for (i, key) in keys.iter().enumerate() {
+        frameworks[0].put(*key, vec![i as u8]).unwrap();
+    }
+
+    // crash the peer closest to the first key, its keys are still on the replicas
+    let crashed = closest(ports.clone(), &keys[0]);
+    frameworks.remove(crashed).stop().unwrap();
+    std::thread::sleep(Duration::from_millis(1000));
+
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(frameworks[i % frameworks.len()].get(*key).unwrap(), Some(vec![i as u8]));
+    }
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_handoff() {
+    let ports = 47150..47158;
+    let (mut frameworks, _) = start_network(ports.start..ports.start + 4);
+
+    let keys: Vec<Id> = (0..32).map(Id::from_key).collect();
+    for (i, key) in keys.iter().enumerate() {
+        frameworks[0].put(*key, vec![i as u8]).unwrap();
+    }
+
+    // the new peers take over the keys they are the closest to
+    for port in ports.start + 4..ports.end {
+        let mut framework = Framework::new(config(port)).unwrap();
+        framework.start().unwrap();
+        framework.join(config(ports.start).bind_addr).unwrap();
+        frameworks.push(framework);
+    }
+    std::thread::sleep(Duration::from_millis(500));
+
+    // every original peer leaves, the keys must survive on the new ones
+    for mut framework in frameworks.drain(..4) {
+        framework.leave().unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+    }
+
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(frameworks[i % frameworks.len()].get(*key).unwrap(), Some(vec![i as u8]));
+    }
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_anti_entropy() {
+    let ports = 47160..47164;
+    let (mut frameworks, _) = start_network(ports.clone());
+
+    let key = Id::from_key(0);
+    let root = closest(ports.clone(), &key);
+    let root_id = Peer::new(config(ports.start + root as u16).bind_addr).id();
+    let mut others: Vec<usize> = (0..frameworks.len()).filter(|node| *node != root).collect();
+    others.sort_by_key(|node| Peer::new(config(ports.start + *node as u16).bind_addr).id().ring_distance(&root_id));
+    // the peer closest to the root is one of the replicas it compares its keys with
+    let holder = others[0];
+    let same_root = |key: &Id| closest(ports.clone(), key) == root;
+    let missed_key = (1..).map(Id::from_key).find(same_root).unwrap();
+    let deleted_key = (1..).map(Id::from_key).filter(same_root).nth(1).unwrap();
+    frameworks[0].put(key, vec![42]).unwrap();
+    frameworks[0].put(deleted_key, vec![42]).unwrap();
+    frameworks[0].delete(deleted_key).unwrap();
+
+    // the root never received a key that reached the holder, and the replicas receive older writes late
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let old = |value| Entry { value: Some(value), version: 1 };
+    let send = |packet: Packet, node: usize| socket.send_to(&packet.serialize().unwrap(), config(ports.start + node as u16).bind_addr).unwrap();
+    send(Packet::Replicate { key: missed_key, entry: old(vec![7]) }, holder);
+    for node in others.iter() {
+        send(Packet::Replicate { key, entry: old(vec![0]) }, *node);
+        send(Packet::Replicate { key: deleted_key, entry: old(vec![0]) }, *node);
+    }
+    std::thread::sleep(Duration::from_millis(1000));
+
+    // the root pulled the missed key from the holder and sent it to the other replicas before both crashed
+    let (first, second) = (root.max(holder), root.min(holder));
+    frameworks.remove(first).stop().unwrap();
+    frameworks.remove(second).stop().unwrap();
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(frameworks[0].get(missed_key).unwrap(), Some(vec![7]));
+    assert_eq!(frameworks[0].get(key).unwrap(), Some(vec![42]));
+    assert_eq!(frameworks[0].get(deleted_key).unwrap(), None);
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_publish_subscribe() {
+    let ports = 47170..47178;
+    let (mut frameworks, receiver) = start_network(ports.clone());
+
+    let root = closest(ports.clone(), &Id::from_key("news"));
+    let subscribers: Vec<usize> = (0..frameworks.len()).filter(|node| *node != root).step_by(2).collect();
+    for subscriber in subscribers.iter() {
+        frameworks[*subscriber].subscribe(1, "news").unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(200));
+
+    let receive = |count: usize| {
+        let mut nodes: Vec<usize> = (0..count).map(|_| {
+            let (node, key, payload) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+            assert_eq!((key, payload), (Id::from_key("news"), vec![42]));
+            node
+        }).collect();
+        nodes.sort();
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+        nodes
+    };
+    let publisher = (0..frameworks.len()).find(|node| *node != root).unwrap();
+    frameworks[publisher].publish("news", vec![42]).unwrap();
+    assert_eq!(receive(subscribers.len()), subscribers);
+
+    // the tree is repaired after its root crashes
+    frameworks[root].stop().unwrap();
+    std::thread::sleep(Duration::from_millis(1000));
+    frameworks[publisher].publish("news", vec![42]).unwrap();
+    assert_eq!(receive(subscribers.len()), subscribers);
+
+    frameworks[subscribers[0]].unsubscribe(1, "news").unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    frameworks[publisher].publish("news", vec![42]).unwrap();
+    assert_eq!(receive(subscribers.len() - 1), subscribers[1..]);
+
+    for (node, mut framework) in frameworks.into_iter().enumerate() {
+        if node != root {
+            framework.stop().unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_anycast() {
+    let ports = 47180..47188;
+    let (frameworks, receiver) = start_network(ports.clone());
+
+    let root = closest(ports.clone(), &Id::from_key("service"));
+    let members: Vec<usize> = (0..frameworks.len()).filter(|node| *node != root).take(2).collect();
+    for member in members.iter() {
+        frameworks[*member].subscribe(1, "service").unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(200));
+
+    // every anycast reaches exactly one member, a member receives its own anycasts
+    for (node, framework) in frameworks.iter().enumerate() {
+        framework.anycast("service", vec![node as u8]).unwrap();
+        let (receiver_node, key, payload) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(members.contains(&receiver_node));
+        if members.contains(&node) {
+            assert_eq!(receiver_node, node);
+        }
+        assert_eq!((key, payload), (Id::from_key("service"), vec![node as u8]));
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    // without members the message is dropped
+    frameworks[0].anycast("nobody", vec![0]).unwrap();
+    assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_broadcast() {
+    let ports = 47190..47198;
+    let (frameworks, receiver) = start_network(ports.clone());
+
+    for (node, framework) in frameworks.iter().enumerate() {
+        let key = Id::from_key(node);
+        framework.broadcast(1, key, vec![node as u8]).unwrap();
+        let mut nodes: Vec<usize> = (0..frameworks.len()).map(|_| {
+            let (receiver_node, delivered_key, payload) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+            assert_eq!((delivered_key, payload), (key, vec![node as u8]));
+            receiver_node
+        }).collect();
+        nodes.sort();
+        assert_eq!(nodes, (0..frameworks.len()).collect::<Vec<_>>());
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_send_reliable() {
+    let ports = 47200..47208;
+    let (frameworks, receiver) = start_network(ports.clone());
+
+    for i in 0..16 {
+        let key = Id::from_key(i);
+        frameworks[i % frameworks.len()].send_reliable(1, key, vec![i as u8]).unwrap();
+        let (node, delivered_key, payload) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!((node, delivered_key, payload), (closest(ports.clone(), &key), key, vec![i as u8]));
+    }
+    assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+
+    // nobody acknowledges a message for an application that is not registered
+    let key = Id::from_key("unknown");
+    let sender = (closest(ports.clone(), &key) + 1) % frameworks.len();
+    assert!(frameworks[sender].send_reliable(2, key, vec![0]).is_err());
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_large_payloads() {
+    let ports = 47210..47214;
+    let (frameworks, receiver) = start_network(ports.clone());
+
+    let payload: Vec<u8> = (0..20000).map(|i| (i % 251) as u8).collect();
+    for i in 0..8 {
+        let key = Id::from_key(i);
+        frameworks[i % frameworks.len()].send(1, key, payload.clone()).unwrap();
+        let (node, delivered_key, delivered_payload) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!((node, delivered_key), (closest(ports.clone(), &key), key));
+        assert!(delivered_payload == payload);
+
+        frameworks[i % frameworks.len()].put(key, payload.clone()).unwrap();
+        assert!(frameworks[(i + 1) % frameworks.len()].get(key).unwrap() == Some(payload.clone()));
+    }
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_request() {
+    let ports = 47220..47228;
+    let (frameworks, _) = start_network(ports.clone());
+
+    for i in 0..16 {
+        let key = Id::from_key(i);
+        let response = frameworks[i % frameworks.len()].request(1, key, vec![i as u8]).unwrap();
+        assert_eq!(response, vec![closest(ports.clone(), &key) as u8, i as u8]);
+    }
+    assert!(frameworks[0].request(2, Id::from_key(0), vec![0]).is_err());
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+/// Answers every request with the address of the requester.
+struct Requester;
+
+impl Application for Requester {
+    fn deliver(&self, _key: Id, _payload: Vec<u8>) {}
+
+    fn request(&self, _key: Id, _payload: Vec<u8>, origin: &Peer) -> Option<Vec<u8>> {
+        Some(origin.addr().to_string().into_bytes())
+    }
+}
+
+#[test]
+fn test_public_addr() {
+    let ports = 47300..47302;
+    let mut first = Framework::new(config(ports.start)).unwrap();
+    first.register_application(1, Requester).unwrap();
+    first.start().unwrap();
+    first.bootstrap(config(ports.start).bind_addr).unwrap();
+    // bound to every interface, the node is reached at the address the entry point saw
+    let public_addr = config(ports.start + 1).bind_addr;
+    let mut second = Framework::new(Config { bind_addr: format!("0.0.0.0:{}", ports.start + 1).parse().unwrap(), ..config(ports.start + 1) }).unwrap();
+    second.register_application(1, Requester).unwrap();
+    second.start().unwrap();
+    second.join(config(ports.start).bind_addr).unwrap();
+    assert_eq!(second.local_peer(), Some(Peer::new(public_addr)));
+
+    // the requester given to the application is reachable, whichever node is the root of the key
+    for i in 0..8 {
+        let response = second.request(1, Id::from_key(i), Vec::new()).unwrap();
+        assert_eq!(response, public_addr.to_string().into_bytes());
+    }
+
+    first.stop().unwrap();
+    second.stop().unwrap();
+}
+
+#[test]
+fn test_lookup() {
+    let ports = 47230..47238;
+    let (frameworks, _) = start_network(ports.clone());
+
+    for i in 0..16 {
+        let key = Id::from_key(i);
+        let node = i % frameworks.len();
+        let (root, hop_count) = frameworks[node].lookup(key).unwrap();
+        let closest = closest(ports.clone(), &key);
+        assert_eq!(root, Peer::new(config(ports.start + closest as u16).bind_addr));
+        assert_eq!(hop_count == 0, closest == node);
+    }
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_lookup_public_addr() {
+    let port = 47310;
+    let public_addr = config(port).bind_addr;
+    let mut framework = Framework::new(Config { bind_addr: format!("0.0.0.0:{}", port).parse().unwrap(), ..config(port) }).unwrap();
+    framework.start().unwrap();
+    framework.bootstrap(public_addr).unwrap();
+
+    // the only node is the root of every key, it answers with the address it is reached at, not the wildcard one
+    assert_eq!(framework.lookup(Id::from_key(0)).unwrap(), (Peer::new(public_addr), 0));
+
+    framework.stop().unwrap();
+}
+
+/// Blocks the thread handling its messages until it is released.
+struct Blocker {
+    blocked: Mutex<mpsc::Sender<()>>,
+    release: Mutex<mpsc::Receiver<()>>,
+}
+
+impl Application for Blocker {
+    fn deliver(&self, _key: Id, _payload: Vec<u8>) {
+        self.blocked.lock().unwrap().send(()).unwrap();
+        self.release.lock().unwrap().recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+}
+
+#[test]
+fn test_slow_handler() {
+    let ports = 47250..47258;
+    let (frameworks, receiver) = start_network(ports.clone());
+    let key = (0..).map(Id::from_key).find(|key| closest(ports.clone(), key) != 0).unwrap();
+    let node = closest(ports.clone(), &key);
+    let (blocked_sender, blocked) = mpsc::channel();
+    let (release, release_receiver) = mpsc::channel();
+    frameworks[node].register_application(2, Blocker { blocked: Mutex::new(blocked_sender), release: Mutex::new(release_receiver) }).unwrap();
+
+    frameworks[0].send(2, key, Vec::new()).unwrap();
+    blocked.recv_timeout(Duration::from_secs(1)).unwrap();
+    // the messages from the other nodes are handled while the handler of the first one is blocked,
+    // except the ones from the nodes sharing its worker which wait in its queue
+    let senders: Vec<usize> = (1..frameworks.len()).filter(|sender| *sender != node).collect();
+    for sender in senders.iter() {
+        frameworks[*sender].send(1, key, vec![*sender as u8]).unwrap();
+    }
+    let (delivered_node, delivered_key, _) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!((delivered_node, delivered_key), (node, key));
+
+    release.send(()).unwrap();
+    for _ in 1..senders.len() {
+        let (delivered_node, _, _) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(delivered_node, node);
+    }
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_memory_transport() {
+    // the ports are not bound, the nodes exchange the packets through channels
+    let ports = 10000..10100;
+    let memory = MemoryNetwork::new();
+    let (frameworks, receiver) = start_network_with(ports.clone(), |port| {
+        // the nodes share the process, they are probed less often to keep the load low
+        let config = Config { probe_interval: Duration::from_secs(1), probe_timeout: Duration::from_secs(1), ..config(port) };
+        let transport = memory.bind(config.bind_addr, config.socket_read_timeout).unwrap();
+        Framework::with_transport(transport, config)
+    });
+
+    for i in 0..100 {
+        let key = Id::from_key(i);
+        frameworks[i % frameworks.len()].send(1, key, vec![i as u8]).unwrap();
+        let (node, delivered_key, payload) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!((node, delivered_key, payload), (closest(ports.clone(), &key), key, vec![i as u8]));
+    }
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[test]
+fn test_fault_injection() {
+    let ports = 11000..11008;
+    let memory = MemoryNetwork::new();
+    let faults = Faults::new(42, FaultRule::default());
+    let (frameworks, receiver) = start_network_with(ports.clone(), |port| {
+        // the peers must survive many lost probes in a row
+        let config = Config { probe_interval: Duration::from_secs(1), max_missed_probes: 10, ..config(port) };
+        let transport = memory.bind(config.bind_addr, config.socket_read_timeout).unwrap();
+        Framework::with_transport(faults.wrap(transport, config.bind_addr), config)
+    });
+
+    // the reliable messages are delivered once even if the datagrams are lost, duplicated, delayed and reordered
+    faults.set_default_rule(FaultRule {
+        drop: 0.1,
+        duplicate: 0.1,
+        reorder: 0.1,
+        corrupt: 0.0,
+        delay: 0.1,
+        min_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(50),
+    });
+    for i in 0..16 {
+        let key = Id::from_key(i);
+        frameworks[i % frameworks.len()].send_reliable(1, key, vec![i as u8]).unwrap();
+        let (node, delivered_key, payload) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!((node, delivered_key, payload), (closest(ports.clone(), &key), key, vec![i as u8]));
+    }
+    assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+    faults.set_default_rule(FaultRule::default());
+
+    // the messages for the node on the other side of a partition are lost until it heals
+    let key = Id::from_key("partition");
+    let isolated = closest(ports.clone(), &key);
+    let sender = (isolated + 1) % frameworks.len();
+    let isolated_addr = config(ports.start + isolated as u16).bind_addr;
+    let others: Vec<SocketAddr> = ports.clone().map(|port| config(port).bind_addr).filter(|addr| *addr != isolated_addr).collect();
+    faults.partition(&[isolated_addr], &others);
+    assert!(frameworks[sender].send_reliable(1, key, vec![0]).is_err());
+    faults.heal();
+    frameworks[sender].send_reliable(1, key, vec![1]).unwrap();
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(1)).unwrap(), (isolated, key, vec![1]));
+
+    for mut framework in frameworks {
+        framework.stop().unwrap();
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_async_framework() {
+    use cactus::network::framework::asynchronous::AsyncFramework;
+
+    // the runtime runs on this thread, the deliveries are polled instead of blocking it
+    async fn next_delivery(receiver: &mpsc::Receiver<Delivery>) -> Delivery {
+        for _ in 0..100 {
+            if let Ok(delivery) = receiver.try_recv() {
+                return delivery;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Nothing was delivered");
+    }
+
+    let ports = 47240..47244;
+    let (sender, receiver) = mpsc::channel();
+    let mut frameworks = Vec::new();
+    for (node, port) in ports.clone().enumerate() {
+        let mut framework = AsyncFramework::new(config(port)).await.unwrap();
+        framework.register_application(1, Collector { node, delivered: Mutex::new(sender.clone()) }).unwrap();
+        framework.start().unwrap();
+        if frameworks.is_empty() {
+            framework.bootstrap(config(port).bind_addr).unwrap();
+        }
+        else {
+            framework.join(config(ports.start).bind_addr).await.unwrap();
+        }
+        frameworks.push(framework);
+    }
+
+    for i in 0..8 {
+        let key = Id::from_key(i);
+        let framework = &frameworks[i % frameworks.len()];
+        framework.send(1, key, vec![i as u8]).await.unwrap();
+        assert_eq!(next_delivery(&receiver).await, (closest(ports.clone(), &key), key, vec![i as u8]));
+
+        let (root, _) = framework.lookup(key).await.unwrap();
+        assert_eq!(root.addr(), config(ports.start + closest(ports.clone(), &key) as u16).bind_addr);
+
+        framework.put(key, vec![i as u8]).await.unwrap();
+        assert_eq!(frameworks[(i + 1) % frameworks.len()].get(key).await.unwrap(), Some(vec![i as u8]));
+    }
+
+    // cancelling the token stops the tasks, shutdown waits for them
+    let mut cancelled = frameworks.pop().unwrap();
+    cancelled.cancellation_token().cancel();
+    cancelled.shutdown().await.unwrap();
+    assert!(cancelled.start().is_err());
+    assert!(cancelled.leave().await.is_err());
+
+    for mut framework in frameworks {
+        framework.leave().await.unwrap();
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_async_lookup_public_addr() {
+    use cactus::network::framework::asynchronous::AsyncFramework;
+
+    let port = 47311;
+    let public_addr = config(port).bind_addr;
+    let mut framework = AsyncFramework::new(Config { bind_addr: format!("0.0.0.0:{}", port).parse().unwrap(), ..config(port) }).await.unwrap();
+    // a framework that was never started does not join or leave
+    assert!(framework.join(config(port + 1).bind_addr).await.is_err());
+    assert!(framework.leave().await.is_err());
+    framework.start().unwrap();
+    framework.bootstrap(public_addr).unwrap();
+
+    assert_eq!(framework.lookup(Id::from_key(0)).await.unwrap(), (Peer::new(public_addr), 0));
+
+    framework.shutdown().await.unwrap();
+}
+
+fn simulate_churn(seed: u64, nodes: usize, crashes: usize) -> anyhow::Result<(Simulator, Stats)> {
+    let link = Link { min_latency: Duration::from_millis(5), max_latency: Duration::from_millis(50), loss: 0.01 };
+    let config = Config { probe_interval: Duration::from_secs(1), probe_timeout: Duration::from_millis(500), ..config(0) };
+    let mut simulator = Simulator::new(seed, link, config);
+    simulator.network().enable_trace();
+    let addr = |i: usize| -> SocketAddr { format!("10.0.{}.{}:4848", i / 0x100, i % 0x100).parse().unwrap() };
+    for i in 0..nodes {
+        simulator.add_node(addr(i))?;
+    }
+    simulator.run_for(Duration::from_secs(5));
+    simulator.check_routing(500)?;
+
+    // crash some nodes and replace them, the routes must converge again once the failures are detected
+    for i in nodes..nodes + crashes {
+        let crashed = simulator.random_node().unwrap();
+        simulator.crash_node(&crashed)?;
+        simulator.add_node(addr(i))?;
+    }
+    simulator.run_for(Duration::from_secs(10));
+    simulator.check_routing(500)?;
+    let stats = simulator.network().stats();
+    Ok((simulator, stats))
+}
+
+#[test]
+fn test_simulation_churn() {
+    let (simulator, stats) = simulate_churn(7, 64, 16).unwrap();
+    assert_eq!(simulator.nodes().len(), 64);
+    assert!(stats.lost > 0);
+}
+
+#[test]
+fn test_simulation_is_deterministic() {
+    let (first, first_stats) = simulate_churn(42, 16, 4).unwrap();
+    let (second, second_stats) = simulate_churn(42, 16, 4).unwrap();
+    assert_eq!(first.nodes(), second.nodes());
+    assert_eq!(first_stats, second_stats);
+    // every datagram is the same, ids and nonces included, and sent at the same time
+    let (first_trace, second_trace) = (first.network().trace(), second.network().trace());
+    assert_eq!(first_trace.len(), second_trace.len());
+    assert!(first_trace == second_trace);
+    let (_, other_stats) = simulate_churn(43, 16, 4).unwrap();
+    assert_ne!(first_stats, other_stats);
+}
+